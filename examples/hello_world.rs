@@ -12,12 +12,11 @@ use std::env;
 use tokio_core::reactor::Core;
 use tokio_core::net::{TcpListener};
 use tokio_core::io::Io;
-use futures::{Stream, Future};
 use futures::future::{FutureResult, ok};
 
 use tk_http::{Status};
 use tk_http::server::buffered::{Request, BufferedDispatcher};
-use tk_http::server::{Encoder, EncoderDone, Config, Proto, Error};
+use tk_http::server::{Encoder, EncoderDone, Config, Error, serve};
 
 
 const BODY: &'static str = "Hello World!";
@@ -50,17 +49,11 @@ fn main() {
     let listener = TcpListener::bind(&addr, &lp.handle()).unwrap();
     let cfg = Config::new().done();
     let h1 = lp.handle();
+    let h2 = lp.handle();
 
-    let done = listener.incoming()
-        .map_err(|e| { println!("Accept error: {}", e); })
-        .map(move |(socket, addr)| {
-            Proto::new(socket, &cfg,
-                BufferedDispatcher::new(addr, &h1, || service),
-                &h1)
-            .map_err(|e| { println!("Connection error: {}", e); })
-        })
-        .buffer_unordered(200000)
-          .for_each(|()| Ok(()));
+    let done = serve(listener, &cfg,
+        move |addr| BufferedDispatcher::new(addr, &h1, || service),
+        &h2);
 
     lp.run(done).unwrap();
 }
@@ -0,0 +1,43 @@
+//! Base64 encoding used for basic authentication headers
+//!
+
+
+/// Encodes data using the standard alphabet with padding
+pub fn encode(data: &[u8]) -> String {
+    const CHARS: &'static[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                                  abcdefghijklmnopqrstuvwxyz\
+                                  0123456789+/";
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as usize) << 16 |
+            (*chunk.get(1).unwrap_or(&0) as usize) << 8 |
+            *chunk.get(2).unwrap_or(&0) as usize;
+        result.push(CHARS[(n >> 18) & 63] as char);
+        result.push(CHARS[(n >> 12) & 63] as char);
+        if chunk.len() > 1 {
+            result.push(CHARS[(n >> 6) & 63] as char);
+        } else {
+            result.push('=');
+        }
+        if chunk.len() > 2 {
+            result.push(CHARS[n & 63] as char);
+        } else {
+            result.push('=');
+        }
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::encode;
+
+    #[test]
+    fn encode_str() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"a"), "YQ==");
+        assert_eq!(encode(b"ab"), "YWI=");
+        assert_eq!(encode(b"Aladdin:open sesame"),
+                   "QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    }
+}
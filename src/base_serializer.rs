@@ -77,6 +77,8 @@ pub enum MessageState {
     FixedBody { is_head: bool, content_length: u64 },
     /// The message contains a chunked body.
     ChunkedBody { is_head: bool },
+    /// The last chunk and some trailers are already written.
+    ChunkedTrailers,
     /// A message in final state.
     Done,
 }
@@ -95,10 +97,29 @@ pub enum Body {
     Request,
 }
 
-fn invalid_header(value: &[u8]) -> bool {
+pub fn invalid_header(value: &[u8]) -> bool {
     return value.iter().any(|&x| x == b'\r' || x == b'\n')
 }
 
+/// Validates a header which is stored to be written later
+///
+/// Body length headers are rejected too, because they are derived from
+/// the body when message is written.
+pub fn check_header(name: &str, value: &[u8]) -> Result<(), HeaderError> {
+    if name.eq_ignore_ascii_case("Content-Length") ||
+        name.eq_ignore_ascii_case("Transfer-Encoding")
+    {
+        return Err(HeaderError::BodyLengthHeader);
+    }
+    if invalid_header(name.as_bytes()) {
+        return Err(HeaderError::InvalidHeaderName);
+    }
+    if invalid_header(value) {
+        return Err(HeaderError::InvalidHeaderValue);
+    }
+    Ok(())
+}
+
 impl MessageState {
     /// Write status line.
     ///
@@ -178,6 +199,9 @@ impl MessageState {
     ///
     /// When the response is already started. It's expected that your response
     /// handler state machine will never call the method twice.
+    ///
+    /// Calling the method when continuation line is already sent does
+    /// nothing.
     pub fn response_continue(&mut self, buf: &mut Buf) {
         use self::MessageState::*;
         match *self {
@@ -187,6 +211,7 @@ impl MessageState {
                                             body: body,
                                             close: close }
             }
+            FinalResponseStart { .. } => {}
             ref state => {
                 panic!("Called continue_line() method on response in state {:?}",
                        state)
@@ -304,6 +329,47 @@ impl MessageState {
         }
     }
 
+    /// Add a trailer to the chunked message.
+    ///
+    /// The last (zero-sized) chunk is written before the first trailer,
+    /// so no more body can be written after this call. Same validation
+    /// rules as in `add_header` apply.
+    ///
+    /// Trailers are silently ignored in responses to HEAD requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_trailer` is called before headers are done or the
+    /// body isn't chunked.
+    pub fn add_trailer(&mut self, buf: &mut Buf, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        use self::HeaderError::*;
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(BodyLengthHeader)
+        }
+        match *self {
+            ChunkedBody { is_head: true } => Ok(()),
+            ChunkedBody { is_head: false } => {
+                let start = buf.len();
+                buf.write_all(b"0\r\n").unwrap();
+                if let Err(e) = self.write_header(buf, name, value) {
+                    buf.remove_range(start..);
+                    return Err(e);
+                }
+                *self = ChunkedTrailers;
+                Ok(())
+            }
+            ChunkedTrailers => self.write_header(buf, name, value),
+            ref state => {
+                panic!("Called add_trailer() method on a message \
+                    in state {:?}", state)
+            }
+        }
+    }
+
     /// Add a content length to the message.
     ///
     /// The `Content-Length` header is written to the output buffer immediately.
@@ -480,7 +546,7 @@ impl MessageState {
     pub fn is_after_headers(&self) -> bool {
         use self::MessageState::*;
         matches!(*self, Bodyless | Done |
-            FixedBody {..} | ChunkedBody {..})
+            FixedBody {..} | ChunkedBody {..} | ChunkedTrailers)
     }
 
     /// Returns true if `done()` method is already called-
@@ -511,6 +577,10 @@ impl MessageState {
                 buf.write(b"0\r\n\r\n").unwrap();
                 *self = Done;
             }
+            ChunkedTrailers => {
+                buf.write(b"\r\n").unwrap();
+                *self = Done;
+            }
             Done => {}  // multiple invocations are okay.
             ref state => {
                 panic!("Called done() method on response in state {:?}",
@@ -641,4 +711,33 @@ mod test {
             msg.done_headers(buf).unwrap();
         })[..], "HTTP/1.1 142 Foo\r\n\r\n".as_bytes());
     }
+
+    #[test]
+    fn chunked_trailers() {
+        assert_eq!(&do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_chunked(buf).unwrap();
+            msg.done_headers(buf).unwrap();
+            msg.write_body(buf, b"Hello");
+            msg.add_trailer(buf, "Grpc-Status", b"0").unwrap();
+            msg.add_trailer(buf, "Grpc-Message", b"ok").unwrap();
+            msg.done(buf);
+        })[..], concat!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n",
+                        "\r\n5\r\nHello\r\n0\r\n",
+                        "Grpc-Status: 0\r\nGrpc-Message: ok\r\n\r\n")
+                .as_bytes());
+    }
+
+    #[test]
+    fn invalid_trailer() {
+        assert_eq!(&do_response11(false, |mut msg, buf| {
+            msg.response_status(buf, 200, "OK");
+            msg.add_chunked(buf).unwrap();
+            msg.done_headers(buf).unwrap();
+            msg.add_trailer(buf, "X-Bad", b"a\r\nb").unwrap_err();
+            msg.add_trailer(buf, "Content-Length", b"0").unwrap_err();
+            msg.done(buf);
+        })[..], concat!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n",
+                        "\r\n0\r\n\r\n").as_bytes());
+    }
 }
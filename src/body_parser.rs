@@ -1,4 +1,3 @@
-use httparse::InvalidChunkSize;
 use tokio_core::io::Io;
 use tk_bufstream::ReadBuf;
 
@@ -25,7 +24,7 @@ impl BodyProgress {
         }
     }
     pub fn parse<S: Io>(&mut self, io: &mut ReadBuf<S>)
-        -> Result<(), InvalidChunkSize>
+        -> Result<(), chunked::Error>
     {
         use self::BodyProgress::*;
         match *self {
@@ -35,6 +34,14 @@ impl BodyProgress {
         }
         Ok(())
     }
+    /// Returns trailers of the chunked body (empty for other kinds of body)
+    pub fn trailers(&self) -> &[(String, Vec<u8>)] {
+        use self::BodyProgress::*;
+        match *self {
+            Chunked(ref s) => s.trailers(),
+            Fixed(_) | Eof => &[],
+        }
+    }
     pub fn consume<S: Io>(&mut self, io: &mut ReadBuf<S>, n: usize) {
         use self::BodyProgress::*;
         io.in_buf.consume(n);
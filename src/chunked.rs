@@ -1,13 +1,35 @@
-use httparse::{InvalidChunkSize, parse_chunk_size};
+use httparse::{self, InvalidChunkSize, parse_chunk_size, EMPTY_HEADER};
 use tk_bufstream::Buf;
 
 
+/// A hard limit on the number of trailer fields
+const MAX_TRAILERS: usize = 64;
+
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum Error {
+        ChunkSize(err: InvalidChunkSize) {
+            description("invalid chunk size")
+            from()
+        }
+        Trailers(err: httparse::Error) {
+            description("invalid trailers")
+            display("invalid trailers: {:?}", err)
+            from()
+        }
+    }
+}
+
 // TODO(tailhook) review usizes here, probaby we may accept u64
 #[derive(Debug, Clone, PartialEq)]
 pub struct State {
     buffered: usize,
     pending: usize,
+    /// Zero-size chunk is received, trailers are expected now
+    last_chunk: bool,
     done: bool,
+    trailers: Vec<(String, Vec<u8>)>,
 }
 
 impl State {
@@ -15,22 +37,43 @@ impl State {
         State {
             buffered: 0,
             pending: 0,
+            last_chunk: false,
             done: false,
+            trailers: Vec::new(),
         }
     }
-    pub fn parse(&mut self, buf: &mut Buf) -> Result<(), InvalidChunkSize> {
-        let State { ref mut buffered, ref mut pending, ref mut done } = *self;
+    pub fn parse(&mut self, buf: &mut Buf) -> Result<(), Error> {
+        let State { ref mut buffered, ref mut pending, ref mut last_chunk,
+                    ref mut done, ref mut trailers } = *self;
         if *done {
             return Ok(());
         }
         while *buffered < buf.len() {
-            if *pending == 0 {
+            if *last_chunk {
+                let bytes = {
+                    let mut headers = [EMPTY_HEADER; MAX_TRAILERS];
+                    let result = httparse::parse_headers(&buf[*buffered..],
+                                                         &mut headers)?;
+                    match result {
+                        httparse::Status::Complete((bytes, headers)) => {
+                            trailers.extend(headers.iter().map(|h| {
+                                (h.name.to_string(), h.value.to_vec())
+                            }));
+                            bytes
+                        }
+                        httparse::Status::Partial => return Ok(()),
+                    }
+                };
+                buf.remove_range(*buffered..*buffered+bytes);
+                *done = true;
+                return Ok(());
+            } else if *pending == 0 {
                 use httparse::Status::*;
                 match parse_chunk_size(&buf[*buffered..])? {
                     Complete((bytes, 0)) => {
                         buf.remove_range(
                             *buffered..*buffered+bytes);
-                        *done = true;
+                        *last_chunk = true;
                     }
                     Complete((bytes, chunk_size)) => {
                         // TODO(tailhook) optimized multiple removes
@@ -63,6 +106,9 @@ impl State {
     pub fn is_done(&self) -> bool {
         self.done
     }
+    pub fn trailers(&self) -> &[(String, Vec<u8>)] {
+        &self.trailers
+    }
     pub fn consume(&mut self, n: usize) {
         assert!(self.buffered >= n);
         self.buffered -= n;
@@ -80,12 +126,44 @@ mod test {
         let mut buf = Buf::new();
         buf.extend(b"4\r\nhell\r\n");
         assert_eq!(state.parse(&mut buf), Ok(()));
-        assert_eq!(state, State { buffered: 4, pending: 0, done: false });
+        assert_eq!(state.buffered, 4);
+        assert!(!state.is_done());
         state.consume(4);
         buf.consume(4);
         assert_eq!(state.buffered, 0);
         buf.extend(b"0\r\n");
         assert_eq!(state.parse(&mut buf), Ok(()));
-        assert_eq!(state, State { buffered: 0, pending: 0, done: true });
+        assert!(!state.is_done());
+        buf.extend(b"\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(state.is_done());
+        assert_eq!(buf.len(), 0);
+        assert_eq!(state.trailers().len(), 0);
+    }
+
+    #[test]
+    fn trailers() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"4\r\nhell\r\n0\r\nGrpc-Status: 0\r\n");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert_eq!(state.buffered, 4);
+        assert!(!state.is_done());
+        buf.extend(b"Checksum: abc\r\n\r\nGET");
+        assert_eq!(state.parse(&mut buf), Ok(()));
+        assert!(state.is_done());
+        assert_eq!(&buf[..], b"hellGET");
+        assert_eq!(state.trailers(), &[
+            ("Grpc-Status".to_string(), b"0".to_vec()),
+            ("Checksum".to_string(), b"abc".to_vec()),
+        ][..]);
+    }
+
+    #[test]
+    fn invalid_trailers() {
+        let mut state = State::new();
+        let mut buf = Buf::new();
+        buf.extend(b"0\r\nbad header\r\n\r\n");
+        assert!(state.parse(&mut buf).is_err());
     }
 }
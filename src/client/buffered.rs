@@ -9,26 +9,55 @@
 //! but requires more boilerplate. You can mix and match different
 //! styles on single HTTP connection.
 //!
-use url::Url;
-use futures::Async;
-use futures::future::{FutureResult, ok};
+use std::mem;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::str::from_utf8;
+use std::ascii::AsciiExt;
+
+use url::{Url, Position};
+use futures::{Async, AsyncSink, Future, Poll, Sink};
+use futures::future::{FutureResult, IntoFuture, ok, err};
 use futures::sync::oneshot::{channel, Sender, Receiver};
 use tokio_core::io::Io;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
 
-use enums::Status;
-use enums::Version;
+use enums::{Status, Method};
+use base_serializer::{HeaderError, check_header};
 use client::{Error, Codec, Encoder, EncoderDone, Head, RecvMode};
+use client::{Config, Proto, HttpProxy};
+use client::proxy::connection_options;
 use client::errors::ErrorEnum;
+use client::encoder::request_head;
+use client::pool::connect_tcp;
+use client::decompress::{Decoder, ACCEPT_ENCODING};
+use cookie::CookieJar;
+
+
+/// Headers that are not sent to other origin on redirect
+const SENSITIVE_HEADERS: &'static [&'static str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+];
 
 /// Fully buffered (in-memory) writing request and reading response
 ///
 /// This coded should be used when you don't have any special needs
 pub struct Buffered {
-    method: &'static str,
+    method: Method,
     url: Url,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Option<Vec<u8>>,
     sender: Option<Sender<Result<Response, Error>>>,
     response: Option<Response>,
     max_response_length: usize,
+    cookies: Option<Arc<Mutex<CookieJar>>>,
+    decompress: bool,
+    decoder: Option<Decoder>,
+    proxy: Option<HttpProxy>,
 }
 
 #[derive(Debug)]
@@ -37,6 +66,7 @@ pub struct Response {
     status: Status,
     headers: Vec<(String, Vec<u8>)>,
     body: Vec<u8>,
+    trailers: Vec<(String, Vec<u8>)>,
 }
 
 impl Response {
@@ -52,28 +82,97 @@ impl Response {
     pub fn body(&self) -> &[u8] {
         &self.body
     }
+    /// Get response trailers (only chunked responses may have ones)
+    pub fn trailers(&self) -> &[(String, Vec<u8>)] {
+        &self.trailers
+    }
 }
 
 impl<S: Io> Codec<S> for Buffered {
     type Future = FutureResult<EncoderDone<S>, Error>;
     fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
-        e.request_line(self.method, self.url.path(), Version::Http11);
-        self.url.host_str().map(|x| {
-            e.add_header("Host", x).unwrap();
-        });
-        e.done_headers().unwrap();
+        let proxy = match self.via_proxy() {
+            Ok(proxy) => proxy,
+            Err(e) => return err(e),
+        };
+        match proxy {
+            Some(proxy) => {
+                request_head(&mut e, self.method.clone(),
+                    &proxy.request_target(&self.url), &self.url);
+            }
+            None => {
+                request_head(&mut e, self.method.clone(),
+                    &self.url[Position::BeforePath..Position::AfterQuery],
+                    &self.url);
+            }
+        }
+        let options = match proxy {
+            Some(_) => connection_options(&self.headers),
+            None => Vec::new(),
+        };
+        for &(ref name, ref value) in &self.headers {
+            if let Some(proxy) = proxy {
+                if proxy.skip_header(name) ||
+                    options.iter().any(|x| x.eq_ignore_ascii_case(name))
+                {
+                    continue;
+                }
+            }
+            e.add_header(name, value).unwrap();
+        }
+        if let Some(value) = proxy.and_then(|p| p.authorization()) {
+            e.add_header("Proxy-Authorization", value).unwrap();
+        }
+        if let Some(ref jar) = self.cookies {
+            let jar = jar.lock().expect("cookie jar is not poisoned");
+            if let Some(value) = jar.header(&self.url) {
+                e.add_header("Cookie", value).unwrap();
+            }
+        }
+        if self.decompress && !self.headers.iter()
+            .any(|&(ref name, _)| name.eq_ignore_ascii_case("Accept-Encoding"))
+        {
+            e.add_header("Accept-Encoding", ACCEPT_ENCODING).unwrap();
+        }
+        match self.body {
+            Some(ref body) => {
+                e.add_length(body.len() as u64).unwrap();
+                e.done_headers().unwrap();
+                e.write_body(body);
+            }
+            None => e.done_headers().unwrap(),
+        }
         ok(e.done())
     }
     fn headers_received(&mut self, headers: &Head) -> Result<RecvMode, Error> {
         let status = headers.status()
             .ok_or(ErrorEnum::InvalidStatus)?;
+        self.decoder = if self.decompress {
+            Decoder::from_headers(headers)
+        } else {
+            None
+        };
+        if let Some(ref mut decoder) = self.decoder {
+            decoder.limit(self.max_response_length as u64);
+        }
+        let decoded = self.decoder.is_some();
         self.response = Some(Response {
             status: status,
-            headers: headers.headers().map(|(k, v)| {
-                (k.to_string(), v.to_vec())
-            }).collect(),
+            headers: headers.headers()
+                .filter(|&(k, _)| {
+                    !decoded ||
+                    !k.eq_ignore_ascii_case("Content-Encoding") &&
+                    !k.eq_ignore_ascii_case("Content-Length")
+                })
+                .map(|(k, v)| (k.to_string(), v.to_vec()))
+                .collect(),
             body: Vec::new(),
+            trailers: Vec::new(),
         });
+        if let Some(ref jar) = self.cookies {
+            jar.lock().expect("cookie jar is not poisoned")
+                .store(&self.url, self.response.as_ref().unwrap().headers());
+        }
         Ok(RecvMode::buffered(self.max_response_length))
     }
     fn data_received(&mut self, data: &[u8], end: bool)
@@ -81,27 +180,689 @@ impl<S: Io> Codec<S> for Buffered {
     {
         assert!(end);
         let mut response = self.response.take().unwrap();
-        response.body = data.to_vec();
+        match self.decoder {
+            Some(ref mut decoder) => {
+                decoder.decode(data, &mut response.body)?;
+                decoder.finish()?;
+            }
+            None => response.body = data.to_vec(),
+        }
         self.sender.take().unwrap().complete(Ok(response));
         Ok(Async::Ready(data.len()))
     }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        self.response.as_mut().unwrap().trailers = trailers.to_vec();
+        Ok(())
+    }
+    fn authority(&self) -> Option<String> {
+        self.target_authority().ok()
+    }
 }
 
 impl Buffered {
-    /// Fetch data from url using GET method, fully buffered
-    pub fn get(url: Url) -> (Buffered, Receiver<Result<Response, Error>>) {
+    fn new(method: Method, url: Url, body: Option<Vec<u8>>)
+        -> (Buffered, Receiver<Result<Response, Error>>)
+    {
         let (tx, rx) = channel();
         (Buffered {
-                method: "GET",
+                method: method,
                 url: url,
+                headers: Vec::new(),
+                body: body,
                 sender: Some(tx),
                 max_response_length: 10_485_760,
                 response: None,
+                cookies: None,
+                decompress: false,
+                decoder: None,
+                proxy: None,
             },
          rx)
     }
+    /// Fetch data from url using GET method, fully buffered
+    pub fn get(url: Url) -> (Buffered, Receiver<Result<Response, Error>>) {
+        Buffered::new(Method::Get, url, None)
+    }
+    /// Fetch headers of the url using HEAD method
+    ///
+    /// Response body is always empty in this case.
+    pub fn head(url: Url) -> (Buffered, Receiver<Result<Response, Error>>) {
+        Buffered::new(Method::Head, url, None)
+    }
+    /// Send a DELETE request, fully buffered
+    pub fn delete(url: Url)
+        -> (Buffered, Receiver<Result<Response, Error>>)
+    {
+        Buffered::new(Method::Delete, url, None)
+    }
+    /// Send a POST request with the body, fully buffered
+    pub fn post(url: Url, body: Vec<u8>)
+        -> (Buffered, Receiver<Result<Response, Error>>)
+    {
+        Buffered::new(Method::Post, url, Some(body))
+    }
+    /// Send a PUT request with the body, fully buffered
+    pub fn put(url: Url, body: Vec<u8>)
+        -> (Buffered, Receiver<Result<Response, Error>>)
+    {
+        Buffered::new(Method::Put, url, Some(body))
+    }
+    /// Send a PATCH request with the body, fully buffered
+    pub fn patch(url: Url, body: Vec<u8>)
+        -> (Buffered, Receiver<Result<Response, Error>>)
+    {
+        Buffered::new(Method::Patch, url, Some(body))
+    }
+    /// Add a header to the request
+    ///
+    /// `Host` and `Content-Length` are added automatically, so adding
+    /// `Content-Length` or `Transfer-Encoding` here is an error, as well
+    /// as adding newlines in name or value.
+    pub fn add_header<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        check_header(name, value.as_ref())?;
+        self.headers.push((name.to_string(), value.as_ref().to_vec()));
+        Ok(())
+    }
     /// Set max response length for this buffered reader
     pub fn max_response_length(&mut self, value: usize) {
         self.max_response_length = value;
     }
+    /// Ask for compressed response and decompress it
+    ///
+    /// Sends `Accept-Encoding: gzip, deflate` (unless the header is added
+    /// explicitly) and decodes the body if server used one of them. In
+    /// this case `Content-Encoding` and `Content-Length` headers are
+    /// removed from the response, as they describe the compressed body.
+    ///
+    /// Limit set by `max_response_length` applies to both compressed and
+    /// decompressed body.
+    pub fn decompress(&mut self, value: bool) {
+        self.decompress = value;
+    }
+    /// Send cookies from the jar and store ones received in response
+    ///
+    /// When redirects are followed, cookies are matched against the url
+    /// of each hop.
+    pub fn cookie_jar(&mut self, jar: &Arc<Mutex<CookieJar>>) {
+        self.cookies = Some(jar.clone());
+    }
+    /// Send the request through plain HTTP forward proxy
+    ///
+    /// Only `http` urls can be sent through the proxy (in absolute form),
+    /// requests to other urls fail with `ProxyScheme` error, use `Tunnel`
+    /// for them. Hop-by-hop headers, including ones listed in `Connection`
+    /// header, are not sent to the proxy. Both `Pool` and
+    /// `FollowRedirects` connect to the proxy instead of the target host.
+    pub fn http_proxy(&mut self, proxy: &HttpProxy) {
+        self.proxy = Some(proxy.clone());
+    }
+    /// Returns the proxy if request is sent through one
+    ///
+    /// Fails if url can't be requested through the proxy, rather than
+    /// sending request directly.
+    fn via_proxy(&self) -> Result<Option<&HttpProxy>, Error> {
+        match self.proxy {
+            Some(ref proxy) if self.url.scheme() == "http" => Ok(Some(proxy)),
+            Some(_) => Err(ErrorEnum::ProxyScheme.into()),
+            None => Ok(None),
+        }
+    }
+    fn target_authority(&self) -> Result<String, Error> {
+        if let Some(proxy) = self.via_proxy()? {
+            return Ok(proxy.authority().to_string());
+        }
+        match (self.url.host_str(), self.url.port_or_known_default()) {
+            (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+            _ => Err(ErrorEnum::InvalidUrl.into()),
+        }
+    }
+    /// Make a new codec for the same request
+    fn copy(&self) -> (Buffered, Receiver<Result<Response, Error>>) {
+        let (tx, rx) = channel();
+        (Buffered {
+                method: self.method.clone(),
+                url: self.url.clone(),
+                headers: self.headers.clone(),
+                body: self.body.clone(),
+                sender: Some(tx),
+                max_response_length: self.max_response_length,
+                response: None,
+                cookies: self.cookies.clone(),
+                decompress: self.decompress,
+                decoder: None,
+                proxy: self.proxy.clone(),
+            },
+         rx)
+    }
+    /// Turns the request into the next hop if response is a redirect
+    ///
+    /// Returns `false` if response is not a redirect
+    fn follow(&mut self, response: &Response) -> Result<bool, Error> {
+        use enums::Status::{MovedPermanently, Found, SeeOther};
+        use enums::Status::{TemporaryRedirect, PermanentRedirect};
+        let make_get = match response.status() {
+            MovedPermanently | Found => self.method == Method::Post,
+            SeeOther => self.method != Method::Head,
+            TemporaryRedirect | PermanentRedirect => false,
+            _ => return Ok(false),
+        };
+        let location = response.headers().iter()
+            .find(|&&(ref name, _)| name.eq_ignore_ascii_case("Location"))
+            .map(|&(_, ref value)| value);
+        let location = match location {
+            Some(value) => from_utf8(value)
+                .map_err(|_| ErrorEnum::InvalidUrl)?,
+            None => return Ok(false),
+        };
+        let url = self.url.join(location.trim())
+            .map_err(|_| ErrorEnum::InvalidUrl)?;
+        if url.scheme() != self.url.scheme() {
+            return Err(ErrorEnum::RedirectSchemeChanged.into());
+        }
+        if url.origin() != self.url.origin() {
+            self.headers.retain(|&(ref name, _)| {
+                !SENSITIVE_HEADERS.iter()
+                    .any(|h| h.eq_ignore_ascii_case(name))
+            });
+        }
+        if make_get {
+            self.method = Method::Get;
+            self.body = None;
+            self.headers.retain(|&(ref name, _)| {
+                !name.eq_ignore_ascii_case("Content-Type")
+            });
+        }
+        self.url = url;
+        Ok(true)
+    }
+}
+
+/// Follows HTTP redirects for buffered requests
+///
+/// Each hop is sent through a `Proto`. The connection is reused for the
+/// next hop if authority (`host:port`) of the request doesn't change,
+/// otherwise new one is established using the `connector`.
+///
+/// Method is changed to `GET` (and body is dropped) on `303 See Other` and
+/// for `POST` requests on `301` and `302`. On `307` and `308` both method
+/// and body are kept. `Authorization`, `Proxy-Authorization` and `Cookie`
+/// headers are dropped when redirected to another origin.
+///
+/// Redirects that change the scheme of the url (e.g. from `http` to
+/// `https`) fail with `RedirectSchemeChanged` error, because the
+/// connector can't know which protocol to use for the new connection.
+pub struct FollowRedirects<S> {
+    connector: Rc<RefCell<FnMut(&str) -> Box<Future<Item=S, Error=Error>>>>,
+    config: Arc<Config>,
+    handle: Handle,
+    max_redirects: usize,
+}
+
+enum Hop<S: Io> {
+    Connecting(String, Box<Future<Item=S, Error=Error>>),
+    Waiting(Receiver<Result<Response, Error>>),
+    Void,
+}
+
+struct FetchRedirects<S: Io> {
+    request: Buffered,
+    redirects_left: usize,
+    connector: Rc<RefCell<FnMut(&str) -> Box<Future<Item=S, Error=Error>>>>,
+    config: Arc<Config>,
+    handle: Handle,
+    proto: Option<(String, Proto<S, Buffered>)>,
+    state: Hop<S>,
+}
+
+impl<S: Io + 'static> FollowRedirects<S> {
+    /// Create a redirect follower with the connector
+    ///
+    /// The `connector` is called with a `host:port` string and must return
+    /// a future that resolves to a connected transport (same as for
+    /// `Pool::new`). All hops use the scheme of the original request.
+    pub fn new<C>(connector: C, cfg: &Arc<Config>, handle: &Handle)
+        -> FollowRedirects<S>
+        where C: FnMut(&str) -> Box<Future<Item=S, Error=Error>> + 'static,
+    {
+        FollowRedirects {
+            connector: Rc::new(RefCell::new(connector)),
+            config: cfg.clone(),
+            handle: handle.clone(),
+            max_redirects: 10,
+        }
+    }
+    /// Set maximum number of redirects to follow for a single request
+    ///
+    /// Default is 10. When the limit is reached request fails with
+    /// an error.
+    pub fn max_redirects(&mut self, value: usize) -> &mut Self {
+        self.max_redirects = value;
+        self
+    }
+    /// Send a buffered request following redirects
+    ///
+    /// Receiver that is created along with the codec is not used (it's
+    /// canceled), the response is returned by this future instead.
+    pub fn fetch(&self, mut codec: Buffered)
+        -> Box<Future<Item=Response, Error=Error>>
+    {
+        codec.sender = None;
+        let mut fetch = FetchRedirects {
+            request: codec,
+            redirects_left: self.max_redirects,
+            connector: self.connector.clone(),
+            config: self.config.clone(),
+            handle: self.handle.clone(),
+            proto: None,
+            state: Hop::Void,
+        };
+        match fetch.start() {
+            Ok(()) => Box::new(fetch),
+            Err(e) => Box::new(Err(e).into_future()),
+        }
+    }
+    /// Fetch data from url using GET method following redirects
+    pub fn fetch_url(&self, url: &str)
+        -> Box<Future<Item=Response, Error=Error>>
+    {
+        match url.parse() {
+            Ok(url) => self.fetch(Buffered::get(url).0),
+            Err(_) => Box::new(Err(ErrorEnum::InvalidUrl.into())
+                .into_future()),
+        }
+    }
+}
+
+impl FollowRedirects<TcpStream> {
+    /// A convenience method to follow redirects over plain TCP connections
+    ///
+    /// Note: names are resolved using `ToSocketAddrs`, which blocks the
+    /// main loop.
+    pub fn connect_tcp(cfg: &Arc<Config>, handle: &Handle)
+        -> FollowRedirects<TcpStream>
+    {
+        let h = handle.clone();
+        FollowRedirects::new(
+            move |authority: &str| connect_tcp(authority, &h),
+            cfg, handle)
+    }
+}
+
+impl<S: Io> FetchRedirects<S> {
+    /// Sends current request, reusing connection if possible
+    fn start(&mut self) -> Result<(), Error> {
+        let authority = self.request.target_authority()?;
+        if let Some((old, mut proto)) = self.proto.take() {
+            if old == authority && proto.poll_complete().is_ok() {
+                let (codec, rx) = self.request.copy();
+                if let Ok(AsyncSink::Ready) = proto.start_send(codec) {
+                    self.proto = Some((old, proto));
+                    self.state = Hop::Waiting(rx);
+                    return Ok(());
+                }
+            }
+        }
+        let conn = (&mut *self.connector.borrow_mut())(&authority);
+        self.state = Hop::Connecting(authority, conn);
+        Ok(())
+    }
+}
+
+impl<S: Io> Future for FetchRedirects<S> {
+    type Item = Response;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Response, Error> {
+        use self::Hop::*;
+        loop {
+            match mem::replace(&mut self.state, Void) {
+                Connecting(authority, mut conn) => {
+                    let sock = match conn.poll()? {
+                        Async::Ready(sock) => sock,
+                        Async::NotReady => {
+                            self.state = Connecting(authority, conn);
+                            return Ok(Async::NotReady);
+                        }
+                    };
+                    let mut proto = Proto::new(sock,
+                        &self.handle, &self.config);
+                    let (codec, rx) = self.request.copy();
+                    if let AsyncSink::NotReady(_) = proto.start_send(codec)? {
+                        return Err(ErrorEnum::Busy.into());
+                    }
+                    self.proto = Some((authority, proto));
+                    self.state = Waiting(rx);
+                }
+                Waiting(mut rx) => {
+                    let failed = match self.proto {
+                        Some((_, ref mut proto)) => {
+                            proto.poll_complete().is_err()
+                        }
+                        None => false,
+                    };
+                    if failed {
+                        // request is canceled along with the connection
+                        self.proto = None;
+                    }
+                    let response = match rx.poll() {
+                        Ok(Async::Ready(Ok(response))) => response,
+                        Ok(Async::Ready(Err(e))) => return Err(e),
+                        Ok(Async::NotReady) => {
+                            self.state = Waiting(rx);
+                            return Ok(Async::NotReady);
+                        }
+                        Err(_) => return Err(ErrorEnum::Canceled.into()),
+                    };
+                    if !self.request.follow(&response)? {
+                        return Ok(Async::Ready(response));
+                    }
+                    if self.redirects_left == 0 {
+                        return Err(ErrorEnum::TooManyRedirects.into());
+                    }
+                    self.redirects_left -= 1;
+                    self.start()?;
+                }
+                Void => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Async};
+    use futures::future::lazy;
+    use tk_bufstream::MockData;
+    use tokio_core::io::Io;
+    use tokio_core::reactor::Core;
+
+    use enums::{Status, Method};
+    use client::{Config, Error, Codec, Encoder, Head, RecvMode, HttpProxy};
+    use client::mock::{Connections, exchange};
+    use super::{Buffered, Response, FollowRedirects};
+
+    struct Fixture {
+        lp: Core,
+        follow: FollowRedirects<MockData>,
+        connections: Connections,
+    }
+
+    impl Fixture {
+        fn new() -> Fixture {
+            let lp = Core::new().unwrap();
+            let connections = Connections::new();
+            let follow = FollowRedirects::new(connections.connector(),
+                &Config::new().done(), &lp.handle());
+            Fixture {
+                lp: lp,
+                follow: follow,
+                connections: connections,
+            }
+        }
+        fn poll(&mut self, f: &mut Box<Future<Item=Response, Error=Error>>)
+            -> Option<Response>
+        {
+            match self.lp.run(lazy(|| f.poll())).unwrap() {
+                Async::Ready(response) => Some(response),
+                Async::NotReady => None,
+            }
+        }
+        fn respond(&self, idx: usize, data: &str) {
+            self.connections.respond(idx, data);
+        }
+        fn output(&self, idx: usize) -> String {
+            self.connections.take_output(idx)
+        }
+    }
+
+    fn redirect(status: Status, location: &str) -> Response {
+        Response {
+            status: status,
+            headers: vec![("Location".to_string(), location.into())],
+            body: Vec::new(),
+            trailers: Vec::new(),
+        }
+    }
+
+    fn request() -> Buffered {
+        let (mut codec, _) = Buffered::post(
+            "http://example.com/a/b?x=1".parse().unwrap(), b"hello".to_vec());
+        codec.add_header("Content-Type", "text/plain").unwrap();
+        codec.add_header("Authorization", "Basic xxx").unwrap();
+        codec
+    }
+
+    #[test]
+    fn add_header() {
+        let (mut codec, _) = Buffered::get(
+            "http://example.com/".parse().unwrap());
+        assert!(codec.add_header("Content-Length", "10").is_err());
+        assert!(codec.add_header("transfer-encoding", "chunked").is_err());
+        assert!(codec.add_header("X-Test", "a\r\nb").is_err());
+        assert!(codec.add_header("X\nTest", "a").is_err());
+        assert!(codec.add_header("X-Test", "a").is_ok());
+        assert_eq!(codec.headers, vec![
+            ("X-Test".to_string(), b"a".to_vec())]);
+    }
+
+    #[test]
+    fn see_other() {
+        let mut req = request();
+        assert!(req.follow(&redirect(Status::SeeOther, "c?y=2")).unwrap());
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.url.as_str(), "http://example.com/a/c?y=2");
+        assert!(req.body.is_none());
+        assert_eq!(req.headers, vec![
+            ("Authorization".to_string(), b"Basic xxx".to_vec())]);
+    }
+
+    #[test]
+    fn temporary_redirect() {
+        let mut req = request();
+        assert!(req.follow(&redirect(Status::TemporaryRedirect,
+                                     "http://example.com:8080/"))
+                .unwrap());
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.url.as_str(), "http://example.com:8080/");
+        assert_eq!(req.body, Some(b"hello".to_vec()));
+        // authorization is not sent to other origin
+        assert_eq!(req.headers, vec![
+            ("Content-Type".to_string(), b"text/plain".to_vec())]);
+    }
+
+    #[test]
+    fn not_redirect() {
+        let mut req = request();
+        assert!(!req.follow(&redirect(Status::Ok, "/")).unwrap());
+        assert!(!req.follow(&Response {
+            status: Status::Found,
+            headers: Vec::new(),
+            body: Vec::new(),
+            trailers: Vec::new(),
+        }).unwrap());
+        assert_eq!(req.url.as_str(), "http://example.com/a/b?x=1");
+    }
+
+    #[test]
+    fn scheme_change() {
+        let mut req = request();
+        assert!(req.follow(&redirect(Status::PermanentRedirect,
+                                     "https://example.com/a/b?x=1"))
+                .is_err());
+        assert_eq!(req.url.as_str(), "http://example.com/a/b?x=1");
+    }
+
+    #[test]
+    fn fetch_see_other() {
+        let mut f = Fixture::new();
+        let mut fetch = f.follow.fetch(request());
+        assert!(f.poll(&mut fetch).is_none());
+        assert_eq!(f.output(0), "POST /a/b?x=1 HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Content-Type: text/plain\r\n\
+            Authorization: Basic xxx\r\n\
+            Content-Length: 5\r\n\r\nhello");
+        f.respond(0, "HTTP/1.1 303 See Other\r\n\
+                      Location: /c\r\n\
+                      Content-Length: 0\r\n\r\n");
+        assert!(f.poll(&mut fetch).is_none());
+        // same connection is reused for the same host
+        assert_eq!(f.connections.len(), 1);
+        assert_eq!(f.output(0), "GET /c HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Authorization: Basic xxx\r\n\r\n");
+        f.respond(0, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let response = f.poll(&mut fetch).expect("response is ready");
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body(), b"ok");
+    }
+
+    #[test]
+    fn fetch_temporary_redirect() {
+        let mut f = Fixture::new();
+        let mut req = request();
+        req.add_header("Cookie", "session=1").unwrap();
+        let mut fetch = f.follow.fetch(req);
+        assert!(f.poll(&mut fetch).is_none());
+        f.output(0);
+        f.respond(0, "HTTP/1.1 307 Temporary Redirect\r\n\
+                      Location: http://other.example.com/c\r\n\
+                      Content-Length: 0\r\n\r\n");
+        assert!(f.poll(&mut fetch).is_none());
+        assert_eq!(f.connections.len(), 2);
+        assert_eq!(f.connections.authority(1), "other.example.com:80");
+        assert_eq!(f.output(1), "POST /c HTTP/1.1\r\n\
+            Host: other.example.com\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 5\r\n\r\nhello");
+        f.respond(1, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let response = f.poll(&mut fetch).expect("response is ready");
+        assert_eq!(response.body(), b"ok");
+    }
+
+    #[test]
+    fn fetch_scheme_change() {
+        let mut f = Fixture::new();
+        let mut fetch = f.follow.fetch(request());
+        assert!(f.poll(&mut fetch).is_none());
+        f.respond(0, "HTTP/1.1 308 Permanent Redirect\r\n\
+                      Location: https://example.com/a/b?x=1\r\n\
+                      Content-Length: 0\r\n\r\n");
+        assert!(f.lp.run(lazy(|| fetch.poll())).is_err());
+        // body is never sent over a new (plain text) connection
+        assert_eq!(f.connections.len(), 1);
+    }
+
+    #[test]
+    fn proxy_hop_by_hop() {
+        let mut f = Fixture::new();
+        let mut req = request();
+        req.http_proxy(&HttpProxy::new("proxy.local:3128"));
+        req.add_header("Connection", "X-Hop, TE").unwrap();
+        req.add_header("TE", "trailers").unwrap();
+        req.add_header("X-Hop", "1").unwrap();
+        req.add_header("x-end-to-end", "1").unwrap();
+        let mut fetch = f.follow.fetch(req);
+        assert!(f.poll(&mut fetch).is_none());
+        assert_eq!(f.connections.authority(0), "proxy.local:3128");
+        assert_eq!(f.output(0), "POST http://example.com/a/b?x=1 HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Content-Type: text/plain\r\n\
+            Authorization: Basic xxx\r\n\
+            Connection: X-Hop, TE\r\n\
+            x-end-to-end: 1\r\n\
+            Content-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn proxy_scheme() {
+        let mut f = Fixture::new();
+        let (mut req, _) = Buffered::get(
+            "https://example.com/".parse().unwrap());
+        req.http_proxy(&HttpProxy::new("proxy.local:3128"));
+        let mut fetch = f.follow.fetch(req);
+        assert!(f.lp.run(lazy(|| fetch.poll())).is_err());
+        // not connected directly to the target host either
+        assert_eq!(f.connections.len(), 0);
+    }
+
+    #[test]
+    fn proxy_redirect_scheme() {
+        let mut f = Fixture::new();
+        let mut req = request();
+        req.http_proxy(&HttpProxy::new("proxy.local:3128"));
+        let mut fetch = f.follow.fetch(req);
+        assert!(f.poll(&mut fetch).is_none());
+        f.respond(0, "HTTP/1.1 301 Moved Permanently\r\n\
+                      Location: https://example.com/a/b?x=1\r\n\
+                      Content-Length: 0\r\n\r\n");
+        assert!(f.lp.run(lazy(|| fetch.poll())).is_err());
+        assert_eq!(f.connections.len(), 1);
+    }
+
+    /// A wrapper which doesn't forward `trailers_received`
+    struct NoTrailers(Buffered);
+
+    impl<S: Io> Codec<S> for NoTrailers {
+        type Future = <Buffered as Codec<S>>::Future;
+        fn start_write(&mut self, e: Encoder<S>) -> Self::Future {
+            self.0.start_write(e)
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            Codec::<S>::headers_received(&mut self.0, headers)
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            Codec::<S>::data_received(&mut self.0, data, end)
+        }
+    }
+
+    const CHUNKED: &'static str = "HTTP/1.1 200 OK\r\n\
+        Transfer-Encoding: chunked\r\n\r\n\
+        2\r\nok\r\n0\r\nX-Checksum: 1\r\n\r\n";
+
+    #[test]
+    fn trailers() {
+        let (codec, rx) = Buffered::get(
+            "http://example.com/".parse().unwrap());
+        let response = exchange(codec, rx, CHUNKED).unwrap();
+        assert_eq!(response.body(), b"ok");
+        assert_eq!(response.trailers(), &[
+            ("X-Checksum".to_string(), b"1".to_vec())]);
+    }
+
+    #[test]
+    fn wrapper_without_trailers() {
+        let (codec, rx) = Buffered::get(
+            "http://example.com/".parse().unwrap());
+        let response = exchange(NoTrailers(codec), rx, CHUNKED).unwrap();
+        assert_eq!(response.body(), b"ok");
+        assert_eq!(response.trailers(), &[]);
+    }
+
+    #[test]
+    fn decompress() {
+        let mut input = b"HTTP/1.1 200 OK\r\n\
+                          Content-Encoding: gzip\r\n\
+                          Content-Length: 31\r\n\
+                          Content-Type: text/plain\r\n\r\n".to_vec();
+        input.extend_from_slice(&[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03,
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca,
+            0x49, 0x01, 0x00, 0x85, 0x11, 0x4a, 0x0d, 0x0b, 0x00, 0x00,
+            0x00]);
+        let (mut codec, rx) = Buffered::get(
+            "http://example.com/".parse().unwrap());
+        codec.decompress(true);
+        let response = exchange(codec, rx, input).unwrap();
+        assert_eq!(response.body(), b"hello world");
+        assert_eq!(response.headers(), &[
+            ("Content-Type".to_string(), b"text/plain".to_vec())]);
+    }
 }
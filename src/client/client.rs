@@ -1,7 +1,9 @@
 use futures::sink::Sink;
 use futures::future::FutureResult;
+use futures::sync::oneshot::Receiver;
 use futures::{Async, AsyncSink, Future, IntoFuture};
 use tokio_core::io::Io;
+use url::Url;
 
 use client::{Error, Encoder, EncoderDone, Head, RecvMode};
 use client::errors::ErrorEnum;
@@ -71,6 +73,28 @@ pub trait Codec<S: Io> {
     ///
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, Error>;
+
+    /// Trailers of the response received
+    ///
+    /// Called once, when the whole body is received, right before the
+    /// first `data_received` call with `end` set to `true`. So the codec
+    /// may rely on `data_received` alone to complete the response.
+    /// Trailers may only be sent with a chunked body, for other kinds of
+    /// body the list is empty.
+    fn trailers_received(&mut self, _trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        Ok(())
+    }
+
+    /// Returns authority (`host:port`) this request should be sent to
+    ///
+    /// This is used by `Pool` to pick a connection for the request. Default
+    /// implementation returns `None`, which means that request can't be
+    /// sent through a connection pool (but it can be sent to a `Proto`).
+    fn authority(&self) -> Option<String> {
+        None
+    }
 }
 
 impl<S: Io, F> Codec<S> for Box<Codec<S, Future=F>>
@@ -88,6 +112,14 @@ impl<S: Io, F> Codec<S> for Box<Codec<S, Future=F>>
     {
         (**self).data_received(data, end)
     }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        (**self).trailers_received(trailers)
+    }
+    fn authority(&self) -> Option<String> {
+        (**self).authority()
+    }
 }
 
 impl<S: Io, F> Codec<S> for Box<Codec<S, Future=F>+Send>
@@ -105,12 +137,21 @@ impl<S: Io, F> Codec<S> for Box<Codec<S, Future=F>+Send>
     {
         (**self).data_received(data, end)
     }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        (**self).trailers_received(trailers)
+    }
+    fn authority(&self) -> Option<String> {
+        (**self).authority()
+    }
 }
 
 /// A marker trait that applies to a Sink that is essentially a HTTP client
 ///
 /// It may apply to a single connection or a connection pool. For a single
-/// connection the `client::Proto` implements this interface.
+/// connection the `client::Proto` implements this interface, for a pool
+/// it's `client::Pool`.
 ///
 /// We expect a boxed codec here because we assume that different kinds of
 /// requests may be executed though same connection pool. If you want to avoid
@@ -124,6 +165,64 @@ pub trait Client<S: Io, F>: Sink<SinkItem=Box<Codec<S, Future=F>>>
     fn fetch_url(&mut self, url: &str)
         -> Box<Future<Item=buffered::Response, Error=Error>>
         where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Fetch headers of the url using HEAD method
+    fn head_url(&mut self, url: &str)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Send a DELETE request to the url
+    fn delete_url(&mut self, url: &str)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Send a POST request with the body to the url
+    fn post_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Send a PUT request with the body to the url
+    fn put_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Send a PATCH request with the body to the url
+    fn patch_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+
+    /// Send a buffered request and wait for the response
+    ///
+    /// This is useful when request needs some extra headers, for example:
+    ///
+    /// ```rust,ignore
+    /// let (mut codec, receiver) = Buffered::post(url, body);
+    /// codec.add_header("Content-Type", "application/json");
+    /// client.fetch(codec, receiver)
+    /// ```
+    fn fetch(&mut self, codec: buffered::Buffered,
+        receiver: Receiver<Result<buffered::Response, Error>>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>;
+}
+
+/// Parses the url and sends a buffered request made by `new`
+fn fetch_new<C, S, F, N>(client: &mut C, url: &str, new: N)
+    -> Box<Future<Item=buffered::Response, Error=Error>>
+    where C: Client<S, F>,
+          <C as Sink>::SinkError: Into<Error>,
+          S: Io,
+          F: Future<Item=EncoderDone<S>, Error=Error>,
+          N: FnOnce(Url) -> (buffered::Buffered,
+                             Receiver<Result<buffered::Response, Error>>),
+{
+    match url.parse() {
+        Ok(url) => {
+            let (codec, receiver) = new(url);
+            client.fetch(codec, receiver)
+        }
+        Err(_) => Box::new(Err(ErrorEnum::InvalidUrl.into()).into_future()),
+    }
 }
 
 impl<T, S: Io> Client<S, FutureResult<EncoderDone<S>, Error>> for T
@@ -135,14 +234,49 @@ impl<T, S: Io> Client<S, FutureResult<EncoderDone<S>, Error>> for T
         -> Box<Future<Item=buffered::Response, Error=Error>>
         where <Self as Sink>::SinkError: Into<Error>
     {
-        let url = match url.parse() {
-            Ok(u) => u,
-            Err(_) => {
-                return Box::new(Err(ErrorEnum::InvalidUrl.into())
-                    .into_future());
-            }
-        };
-        let (codec, receiver) = buffered::Buffered::get(url);
+        fetch_new(self, url, buffered::Buffered::get)
+    }
+
+    fn head_url(&mut self, url: &str)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
+        fetch_new(self, url, buffered::Buffered::head)
+    }
+
+    fn delete_url(&mut self, url: &str)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
+        fetch_new(self, url, buffered::Buffered::delete)
+    }
+
+    fn post_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
+        fetch_new(self, url, |url| buffered::Buffered::post(url, body))
+    }
+
+    fn put_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
+        fetch_new(self, url, |url| buffered::Buffered::put(url, body))
+    }
+
+    fn patch_url(&mut self, url: &str, body: Vec<u8>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
+        fetch_new(self, url, |url| buffered::Buffered::patch(url, body))
+    }
+
+    fn fetch(&mut self, codec: buffered::Buffered,
+        receiver: Receiver<Result<buffered::Response, Error>>)
+        -> Box<Future<Item=buffered::Response, Error=Error>>
+        where <Self as Sink>::SinkError: Into<Error>
+    {
         match self.start_send(Box::new(codec)) {
             Ok(AsyncSink::NotReady(_)) => {
                 Box::new(Err(ErrorEnum::Busy.into()).into_future())
@@ -158,3 +292,72 @@ impl<T, S: Io> Client<S, FutureResult<EncoderDone<S>, Error>> for T
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Sink};
+    use futures::future::{FutureResult, lazy};
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use client::{Client, Codec, Config, EncoderDone, Error, Proto};
+
+    type Conn = Proto<MockData, Box<Codec<MockData,
+        Future=FutureResult<EncoderDone<MockData>, Error>>>>;
+
+    /// Returns what is written for the request made by `f`
+    fn request<F>(f: F) -> String
+        where F: FnOnce(&mut Conn)
+            -> Box<Future<Item=::client::buffered::Response, Error=Error>>
+    {
+        let mut lp = Core::new().unwrap();
+        let mock = MockData::new();
+        let mut proto: Conn = Proto::new(mock.clone(), &lp.handle(),
+                                         &Config::new().done());
+        lp.run(lazy(|| {
+            let mut response = f(&mut proto);
+            assert!(response.poll().unwrap().is_not_ready());
+            proto.poll_complete()
+        })).unwrap();
+        String::from_utf8(mock.output(..)).unwrap()
+    }
+
+    #[test]
+    fn query() {
+        assert_eq!(request(|c| c.head_url("http://example.com/a?x=1&y")),
+            "HEAD /a?x=1&y HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(request(|c| c.delete_url("http://example.com?x")),
+            "DELETE /?x HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn host_port() {
+        assert_eq!(request(|c| c.fetch_url("http://example.com:8080/")),
+            "GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n");
+        // default port is omitted
+        assert_eq!(request(|c| c.fetch_url("http://example.com:80/")),
+            "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn body() {
+        assert_eq!(request(|c| {
+                c.post_url("http://example.com/x", b"hello".to_vec())
+            }),
+            "POST /x HTTP/1.1\r\nHost: example.com\r\n\
+             Content-Length: 5\r\n\r\nhello");
+        assert_eq!(request(|c| {
+                c.patch_url("http://example.com/x", b"{}".to_vec())
+            }),
+            "PATCH /x HTTP/1.1\r\nHost: example.com\r\n\
+             Content-Length: 2\r\n\r\n{}");
+    }
+
+    #[test]
+    fn invalid_url() {
+        let mut lp = Core::new().unwrap();
+        let mut proto: Conn = Proto::new(MockData::new(), &lp.handle(),
+                                         &Config::new().done());
+        assert!(lp.run(proto.put_url("/relative", Vec::new())).is_err());
+    }
+}
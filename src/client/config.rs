@@ -12,6 +12,7 @@ impl Config {
             keep_alive_timeout: Duration::new(4, 0),
             safe_pipeline_timeout: Duration::from_millis(300),
             max_request_timeout: Duration::new(15, 0),
+            max_connections: 100,
         }
     }
     /// A number of inflight requests until we start returning
@@ -97,6 +98,21 @@ impl Config {
         self
     }
 
+    /// Maximum number of connections a `Pool` may open
+    ///
+    /// This includes connections to all hosts and connections which are
+    /// being established. When limit is reached, idle connections to other
+    /// hosts are closed to make room for new ones.
+    ///
+    /// This is also the number of requests the pool may queue while
+    /// waiting for connections.
+    ///
+    /// This value is not used for a single connection (`Proto`).
+    pub fn max_connections(&mut self, value: usize) -> &mut Self {
+        self.max_connections = value;
+        self
+    }
+
     /// Create a Arc'd config clone to pass to the constructor
     ///
     /// This is just a convenience method.
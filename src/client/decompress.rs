@@ -0,0 +1,558 @@
+//! Decompression of response bodies
+//!
+//! `Buffered` codec decompresses bodies by itself if
+//! `Buffered::decompress(true)` is set. For custom codecs there are two
+//! options:
+//!
+//! 1. Wrap the codec into `Decompressing`, which decodes data before
+//!    passing it to the codec
+//! 2. Use `Decoder` directly in your `Codec::data_received`
+//!
+//! In both cases you need to add `Accept-Encoding` header to the request
+//! yourself.
+//!
+use std::io::{self, Read, BufRead};
+use std::ascii::AsciiExt;
+use std::cmp::max;
+use std::str::from_utf8;
+
+use flate2::{Crc, Decompress, Flush, Status};
+use flate2::bufread::GzDecoder;
+use futures::Async;
+use tokio_core::io::Io;
+
+use client::{Error, Codec, Encoder, Head, RecvMode};
+use client::errors::ErrorEnum;
+
+
+/// Value of `Accept-Encoding` header for supported encodings
+pub const ACCEPT_ENCODING: &'static str = "gzip, deflate";
+
+/// Size of gzip trailer: CRC-32 and size of uncompressed data
+const GZIP_TRAILER: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    /// Zlib stream, or raw deflate stream which some servers send instead
+    Deflate,
+}
+
+#[derive(Debug)]
+enum State {
+    Header,
+    Body,
+    Trailer,
+    Done,
+}
+
+/// Streaming decoder of `gzip` and `deflate` content encodings
+pub struct Decoder {
+    format: Format,
+    state: State,
+    inner: Option<Decompress>,
+    /// Partially received gzip header or trailer
+    buf: Vec<u8>,
+    crc: Crc,
+    total_in: u64,
+    total_out: u64,
+    limit: u64,
+}
+
+/// A codec wrapper that decompresses response body
+///
+/// `Content-Encoding` of the response is checked in `headers_received`,
+/// and if it's `gzip` or `deflate`, the wrapped codec receives decoded
+/// data in `data_received`. Note that headers passed to the wrapped codec
+/// are the original ones, so they still contain `Content-Encoding` and
+/// `Content-Length` of the compressed body.
+pub struct Decompressing<C> {
+    codec: C,
+    decoder: Option<Decoder>,
+    /// Decoded data not consumed by the codec yet
+    buf: Vec<u8>,
+    /// Number of bytes of the input decoded into the `buf`
+    decoded: usize,
+    max_length: u64,
+}
+
+/// Input for parsing gzip header which may be incomplete
+///
+/// End of data is reported as `WouldBlock` rather than as end of file,
+/// so that header parser doesn't accept truncated file name or comment.
+struct Partial<'a>(&'a [u8]);
+
+impl<'a> Read for Partial<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.len() == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.0.read(buf)
+    }
+}
+
+impl<'a> BufRead for Partial<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.0.len() == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(self.0)
+    }
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+/// Returns length of gzip header, or `None` if header is incomplete
+fn gzip_header_length(buf: &[u8]) -> Result<Option<usize>, ()> {
+    let mut input = Partial(buf);
+    match GzDecoder::new(&mut input) {
+        Ok(_) => Ok(Some(buf.len() - input.0.len())),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(_) => Err(()),
+    }
+}
+
+fn read_u32_le(buf: &[u8]) -> u32 {
+    (buf[0] as u32) | (buf[1] as u32) << 8 |
+    (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+/// Returns the value of `Content-Encoding` header, if there is one
+fn content_encoding<'x>(head: &'x Head) -> Option<&'x str> {
+    head.headers()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("Content-Encoding"))
+        .and_then(|(_, value)| from_utf8(value).ok())
+        .map(|value| value.trim())
+}
+
+impl Decoder {
+    /// Create a decoder for the value of `Content-Encoding` header
+    ///
+    /// Returns `None` if encoding is not supported (including `identity`).
+    pub fn new(encoding: &str) -> Option<Decoder> {
+        let format = if encoding.eq_ignore_ascii_case("gzip") ||
+            encoding.eq_ignore_ascii_case("x-gzip")
+        {
+            Format::Gzip
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            Format::Deflate
+        } else {
+            return None;
+        };
+        Some(Decoder {
+            format: format,
+            state: State::Header,
+            inner: None,
+            buf: Vec::new(),
+            crc: Crc::new(),
+            total_in: 0,
+            total_out: 0,
+            limit: u64::max_value(),
+        })
+    }
+    /// Create a decoder for `Content-Encoding` of the response
+    pub fn from_headers(head: &Head) -> Option<Decoder> {
+        content_encoding(head).and_then(Decoder::new)
+    }
+    /// Set limit on the size of decoded data
+    ///
+    /// When limit is exceeded `decode()` returns an error. This protects
+    /// from decompression bombs. There is no limit by default.
+    pub fn limit(&mut self, bytes: u64) -> &mut Self {
+        self.limit = bytes;
+        self
+    }
+    /// Decode a chunk of data appending it to `output`
+    pub fn decode(&mut self, mut data: &[u8], output: &mut Vec<u8>)
+        -> Result<(), Error>
+    {
+        self.total_in += data.len() as u64;
+        while data.len() > 0 {
+            data = match self.state {
+                State::Header => self.header(data, output)?,
+                State::Body => self.body(data, output)?,
+                State::Trailer => self.trailer(data)?,
+                State::Done => match self.format {
+                    // Next gzip member
+                    Format::Gzip => {
+                        self.state = State::Header;
+                        data
+                    }
+                    Format::Deflate => {
+                        return Err(ErrorEnum::DecompressionError.into());
+                    }
+                },
+            };
+        }
+        Ok(())
+    }
+    /// Check that the compressed stream is complete
+    ///
+    /// Call this after the last chunk of data is decoded. Empty stream
+    /// (e.g. a response to `HEAD` request) is considered complete.
+    pub fn finish(&self) -> Result<(), Error> {
+        match self.state {
+            _ if self.total_in == 0 => Ok(()),
+            State::Done => Ok(()),
+            // Some servers pad gzip output with zeros
+            State::Header if self.total_out > 0 &&
+                self.buf.iter().all(|&b| b == 0) => Ok(()),
+            _ => Err(ErrorEnum::DecompressionError.into()),
+        }
+    }
+    fn header<'x>(&mut self, data: &'x [u8], output: &mut Vec<u8>)
+        -> Result<&'x [u8], Error>
+    {
+        let old = self.buf.len();
+        let zlib = match self.format {
+            Format::Gzip => {
+                self.buf.extend_from_slice(data);
+                match gzip_header_length(&self.buf) {
+                    Ok(Some(len)) => {
+                        self.buf.clear();
+                        self.state = State::Body;
+                        self.inner = Some(Decompress::new(false));
+                        self.crc.reset();
+                        return Ok(&data[len - old..]);
+                    }
+                    Ok(None) => return Ok(&[]),
+                    Err(()) if self.buf.iter().all(|&b| b == 0) => {
+                        return Ok(&[]);
+                    }
+                    Err(()) => {
+                        return Err(ErrorEnum::DecompressionError.into());
+                    }
+                }
+            }
+            Format::Deflate => {
+                if old + data.len() < 2 {
+                    self.buf.extend_from_slice(data);
+                    return Ok(&[]);
+                }
+                let first = if old > 0 { self.buf[0] } else { data[0] };
+                let second = if old > 1 { self.buf[1] } else { data[1 - old] };
+                // Zlib header is CMF and FLG bytes with the check bits
+                first & 0x0F == 8 &&
+                    ((first as u16) << 8 | second as u16) % 31 == 0
+            }
+        };
+        self.inner = Some(Decompress::new(zlib));
+        self.state = State::Body;
+        if old == 0 {
+            return Ok(data);
+        }
+        let mut input = self.buf.split_off(0);
+        input.extend_from_slice(data);
+        if self.body(&input, output)?.len() > 0 {
+            // Data after the end of deflate stream
+            return Err(ErrorEnum::DecompressionError.into());
+        }
+        Ok(&[])
+    }
+    fn body<'x>(&mut self, mut data: &'x [u8], output: &mut Vec<u8>)
+        -> Result<&'x [u8], Error>
+    {
+        let inner = self.inner.as_mut().expect("decompressor exists");
+        loop {
+            if output.len() == output.capacity() {
+                let cap = max(output.capacity(), 4096);
+                output.reserve(cap);
+            }
+            let in_before = inner.total_in();
+            let out_before = output.len();
+            let status = inner.decompress_vec(data, output, Flush::None)
+                .map_err(|_| ErrorEnum::DecompressionError)?;
+            data = &data[(inner.total_in() - in_before) as usize..];
+            let produced = output.len() - out_before;
+            if self.format == Format::Gzip {
+                self.crc.update(&output[out_before..]);
+            }
+            self.total_out += produced as u64;
+            if self.total_out > self.limit {
+                return Err(ErrorEnum::ResponseBodyTooLong.into());
+            }
+            if matches!(status, Status::StreamEnd) {
+                self.state = match self.format {
+                    Format::Gzip => State::Trailer,
+                    Format::Deflate => State::Done,
+                };
+                return Ok(data);
+            }
+            if output.len() < output.capacity() &&
+                (data.len() == 0 || inner.total_in() == in_before)
+            {
+                // Need more input
+                return Ok(data);
+            }
+        }
+    }
+    fn trailer<'x>(&mut self, data: &'x [u8]) -> Result<&'x [u8], Error> {
+        let need = GZIP_TRAILER - self.buf.len();
+        if data.len() < need {
+            self.buf.extend_from_slice(data);
+            return Ok(&[]);
+        }
+        self.buf.extend_from_slice(&data[..need]);
+        if read_u32_le(&self.buf[..4]) != self.crc.sum() ||
+            read_u32_le(&self.buf[4..]) != self.crc.amount()
+        {
+            return Err(ErrorEnum::DecompressionError.into());
+        }
+        self.buf.clear();
+        self.state = State::Done;
+        Ok(&data[need..])
+    }
+}
+
+impl<C> Decompressing<C> {
+    /// Wrap the codec
+    pub fn new(codec: C) -> Decompressing<C> {
+        Decompressing {
+            codec: codec,
+            decoder: None,
+            buf: Vec::new(),
+            decoded: 0,
+            max_length: u64::max_value(),
+        }
+    }
+    /// Set limit on the size of decoded body
+    ///
+    /// Limit of `RecvMode::buffered` applies to the compressed body, so
+    /// it's important to set this limit in buffered mode. There is no
+    /// limit by default.
+    pub fn max_length(&mut self, bytes: u64) -> &mut Self {
+        self.max_length = bytes;
+        self
+    }
+    /// Returns a reference to the wrapped codec
+    pub fn get_ref(&self) -> &C {
+        &self.codec
+    }
+    /// Returns a mutable reference to the wrapped codec
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+    /// Unwrap the codec
+    pub fn into_inner(self) -> C {
+        self.codec
+    }
+}
+
+impl<S: Io, C: Codec<S>> Codec<S> for Decompressing<C> {
+    type Future = C::Future;
+    fn start_write(&mut self, e: Encoder<S>) -> C::Future {
+        self.codec.start_write(e)
+    }
+    fn headers_received(&mut self, headers: &Head) -> Result<RecvMode, Error> {
+        self.decoder = Decoder::from_headers(headers);
+        if let Some(ref mut decoder) = self.decoder {
+            decoder.limit(self.max_length);
+        }
+        self.buf.clear();
+        self.decoded = 0;
+        self.codec.headers_received(headers)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        let decoder = match self.decoder {
+            Some(ref mut decoder) => decoder,
+            None => return self.codec.data_received(data, end),
+        };
+        // Data is only consumed when the codec consumes everything decoded
+        // from it, so the same data might be passed again (with more
+        // bytes appended)
+        if data.len() > self.decoded {
+            decoder.decode(&data[self.decoded..], &mut self.buf)?;
+            self.decoded = data.len();
+        }
+        if end {
+            decoder.finish()?;
+        }
+        match self.codec.data_received(&self.buf, end)? {
+            Async::Ready(bytes) => {
+                self.buf.drain(..bytes);
+            }
+            Async::NotReady => return Ok(Async::NotReady),
+        }
+        if self.buf.len() == 0 {
+            let decoded = self.decoded;
+            self.decoded = 0;
+            Ok(Async::Ready(decoded))
+        } else {
+            Ok(Async::Ready(0))
+        }
+    }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        self.codec.trailers_received(trailers)
+    }
+    fn authority(&self) -> Option<String> {
+        self.codec.authority()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::{Compress, Compression, Flush};
+    use futures::Async;
+    use futures::future::FutureResult;
+    use tk_bufstream::MockData;
+    use tokio_core::io::Io;
+
+    use client::{Codec, Encoder, EncoderDone, Error, Head, RecvMode};
+    use client::buffered::Buffered;
+    use client::mock::exchange;
+    use super::{Decoder, Decompressing};
+
+    const GZIP: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03,
+        0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca,
+        0x49, 0x01, 0x00, 0x85, 0x11, 0x4a, 0x0d, 0x0b, 0x00, 0x00, 0x00];
+    const ZLIB: &'static [u8] = &[
+        0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x28, 0xcf,
+        0x2f, 0xca, 0x49, 0x01, 0x00, 0x1a, 0x0b, 0x04, 0x5d];
+    const RAW: &'static [u8] = &[
+        0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca,
+        0x49, 0x01, 0x00];
+
+    fn decode(encoding: &str, data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut decoder = Decoder::new(encoding).unwrap();
+        let mut result = Vec::new();
+        for part in data.chunks(chunk) {
+            decoder.decode(part, &mut result).unwrap();
+        }
+        decoder.finish().unwrap();
+        return result;
+    }
+
+    #[test]
+    fn gzip() {
+        assert_eq!(decode("gzip", GZIP, 100), b"hello world");
+        assert_eq!(decode("x-gzip", GZIP, 1), b"hello world");
+        let mut two = GZIP.to_vec();
+        two.extend_from_slice(GZIP);
+        assert_eq!(decode("gzip", &two, 7), b"hello worldhello world");
+    }
+
+    #[test]
+    fn file_name() {
+        let mut data = GZIP[..10].to_vec();
+        data[3] = 0x08; // FNAME
+        data.extend_from_slice(b"hello.txt\0");
+        data.extend_from_slice(&GZIP[10..]);
+        assert_eq!(decode("gzip", &data, 100), b"hello world");
+        assert_eq!(decode("gzip", &data, 1), b"hello world");
+        assert_eq!(decode("gzip", &data, 13), b"hello world");
+    }
+
+    #[test]
+    fn deflate() {
+        assert_eq!(decode("deflate", ZLIB, 100), b"hello world");
+        assert_eq!(decode("deflate", ZLIB, 1), b"hello world");
+        assert_eq!(decode("deflate", RAW, 100), b"hello world");
+        assert_eq!(decode("deflate", RAW, 1), b"hello world");
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(Decoder::new("identity").is_none());
+        assert!(Decoder::new("br").is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut decoder = Decoder::new("gzip").unwrap();
+        let mut result = Vec::new();
+        decoder.decode(&GZIP[..GZIP.len()-4], &mut result).unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut data = GZIP.to_vec();
+        let len = data.len();
+        data[len - 8] ^= 1;
+        let mut decoder = Decoder::new("gzip").unwrap();
+        assert!(decoder.decode(&data, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn limit() {
+        let mut compress = Compress::new(Compression::Default, true);
+        let mut bomb = Vec::with_capacity(100000);
+        compress.compress_vec(&[0; 1000000], &mut bomb, Flush::Finish);
+        let mut decoder = Decoder::new("deflate").unwrap();
+        decoder.limit(1000);
+        assert!(decoder.decode(&bomb, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(decode("gzip", b"", 1), b"");
+    }
+
+    /// A codec which doesn't consume anything until the end of the body
+    struct Whole {
+        body: Option<Vec<u8>>,
+    }
+
+    impl<S: Io> Codec<S> for Whole {
+        type Future = FutureResult<EncoderDone<S>, Error>;
+        fn start_write(&mut self, _e: Encoder<S>) -> Self::Future {
+            unreachable!();
+        }
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<RecvMode, Error>
+        {
+            Ok(RecvMode::progressive(1))
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            if !end {
+                return Ok(Async::Ready(0));
+            }
+            assert!(self.body.is_none());
+            self.body = Some(data.to_vec());
+            Ok(Async::Ready(data.len()))
+        }
+    }
+
+    #[test]
+    fn partial_consumer() {
+        let mut compress = Compress::new(Compression::Default, true);
+        let mut data = Vec::with_capacity(100);
+        compress.compress_vec(b"hello ", &mut data, Flush::Sync);
+        let half = data.len();
+        compress.compress_vec(b"world", &mut data, Flush::Finish);
+
+        let mut codec = Decompressing::new(Whole { body: None });
+        codec.decoder = Decoder::new("deflate");
+        // the same unconsumed data is passed again with more bytes appended
+        assert!(matches!(Codec::<MockData>::data_received(&mut codec,
+            &data[..half], false), Ok(Async::Ready(0))));
+        assert_eq!(codec.buf, b"hello ");
+        assert!(matches!(Codec::<MockData>::data_received(&mut codec,
+            &data, true), Ok(Async::Ready(x)) if x == data.len()));
+        assert_eq!(codec.get_ref().body.as_ref().unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn wrapped_buffered() {
+        let mut input = b"HTTP/1.1 200 OK\r\n\
+                          Content-Encoding: gzip\r\n\
+                          Content-Length: 31\r\n\r\n".to_vec();
+        input.extend_from_slice(GZIP);
+        let (codec, rx) = Buffered::get(
+            "http://example.com/".parse().unwrap());
+        let response = exchange(Decompressing::new(codec), rx, input)
+            .unwrap();
+        assert_eq!(response.body(), b"hello world");
+        // headers are passed to the wrapped codec as is
+        assert!(response.headers().iter()
+            .any(|&(ref name, _)| name == "Content-Encoding"));
+    }
+}
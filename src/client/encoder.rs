@@ -3,10 +3,12 @@ use std::fmt::Display;
 use std::ascii::AsciiExt;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use futures::{Future, Poll, Async};
 use tokio_core::io::Io;
 use tk_bufstream::WriteBuf;
+use url::Url;
 
-use enums::Version;
+use enums::{Version, Method};
 use headers::is_close;
 use base_serializer::{MessageState, HeaderError};
 
@@ -34,10 +36,32 @@ pub struct EncoderDone<S: Io> {
     buf: WriteBuf<S>,
 }
 
+/// A future that yields `Encoder` again after buffer has less bytes
+///
+/// This future is created by `Encoder::wait_flush(x)`
+pub struct WaitFlush<S: Io>(Option<Encoder<S>>, usize);
+
 pub fn get_inner<S: Io>(e: EncoderDone<S>) -> WriteBuf<S> {
     e.buf
 }
 
+/// Writes request line with the `target` and `Host` header for the `url`
+///
+/// Port is included in `Host` only if it's not the default for the scheme.
+pub fn request_head<S: Io>(e: &mut Encoder<S>, method: Method,
+    target: &str, url: &Url)
+{
+    e.request_line(method, target, Version::Http11);
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => {
+            e.format_header("Host", format_args!("{}:{}", host, port))
+                .unwrap();
+        }
+        (Some(host), None) => e.add_header("Host", host).unwrap(),
+        (None, _) => {}
+    }
+}
+
 impl<S: Io> Encoder<S> {
     /// Write request line.
     ///
@@ -48,11 +72,12 @@ impl<S: Io> Encoder<S> {
     ///
     /// When request line is already written. It's expected that your request
     /// handler state machine will never call the method twice.
-    pub fn request_line(&mut self, method: &str, path: &str, version: Version)
+    pub fn request_line(&mut self, method: Method, path: &str,
+        version: Version)
     {
         self.message.request_line(&mut self.buf.out_buf,
-            method, path, version);
-        let nstatus = if method.eq_ignore_ascii_case("HEAD") {
+            method.as_str(), path, version);
+        let nstatus = if method == Method::Head {
             RequestState::StartedHead as usize
         } else {
             RequestState::StartedNormal as usize
@@ -160,6 +185,21 @@ impl<S: Io> Encoder<S> {
     pub fn write_body(&mut self, data: &[u8]) {
         self.message.write_body(&mut self.buf.out_buf, data)
     }
+    /// Add a trailer to the chunked message
+    ///
+    /// The last chunk is written before the first trailer, so no more
+    /// body can be written after this call. Same validation rules as in
+    /// `add_header` apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_trailer` is called before `done_headers` or
+    /// when the body is not chunked.
+    pub fn add_trailer<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        self.message.add_trailer(&mut self.buf.out_buf, name, value.as_ref())
+    }
     /// Finish writing request and return `EncoderDone` which can be moved to
     ///
     /// # Panics
@@ -169,6 +209,35 @@ impl<S: Io> Encoder<S> {
         self.message.done(&mut self.buf.out_buf);
         EncoderDone { buf: self.buf }
     }
+    /// Returns number of bytes in the output buffer, not yet sent
+    pub fn bytes_buffered(&self) -> usize {
+        self.buf.out_buf.len()
+    }
+    /// Returns a future which yields encoder back when buffer is flushed
+    ///
+    /// More specifically when `bytes_buffered()` is less than or equal
+    /// to the `watermark`. Use it to stream large request body without
+    /// holding it all in memory.
+    pub fn wait_flush(self, watermark: usize) -> WaitFlush<S> {
+        WaitFlush(Some(self), watermark)
+    }
+}
+
+impl<S: Io> Future for WaitFlush<S> {
+    type Item = Encoder<S>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Encoder<S>, io::Error> {
+        let bytes_left = {
+            let enc = self.0.as_mut().expect("future is polled twice");
+            enc.buf.flush()?;
+            enc.buf.out_buf.len()
+        };
+        if bytes_left <= self.1 {
+            Ok(Async::Ready(self.0.take().unwrap()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
 }
 
 pub fn new<S: Io>(io: WriteBuf<S>,
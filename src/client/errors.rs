@@ -6,6 +6,8 @@ use futures::sync::mpsc::SendError;
 use httparse::Error as HttpError;
 use httparse::InvalidChunkSize;
 
+use chunked;
+
 
 /// HTTP client error
 pub struct Error(ErrorEnum);
@@ -33,6 +35,11 @@ quick_error! {
             display("invalid chunk size: {}", err)
             from()
         }
+        /// Bad trailers of the chunked response received
+        Trailers(err: HttpError) {
+            description("bad trailers")
+            display("bad trailers: {}", err)
+        }
         /// Bad `Content-Length` header
         BadContentLength {
             description("bad content length")
@@ -95,6 +102,21 @@ quick_error! {
         InvalidStatus {
             description("unsupported status")
         }
+        /// Redirect limit of `FollowRedirects` is reached
+        TooManyRedirects {
+            description("too many redirects")
+        }
+        /// Redirect to another scheme (e.g. from `http` to `https`)
+        ///
+        /// Connector of `FollowRedirects` only receives `host:port`, so
+        /// such redirect can't be followed safely
+        RedirectSchemeChanged {
+            description("redirect changes url scheme")
+        }
+        /// Request body doesn't match the length specified upfront
+        RequestBodyLength {
+            description("request body length doesn't match content length")
+        }
         /// Request timed out
         RequestTimeout {
             description("request timed out")
@@ -103,6 +125,19 @@ quick_error! {
         KeepAliveTimeout {
             description("connection timed out beeing on keep-alive")
         }
+        /// Proxy responded to `CONNECT` with non-2xx status
+        TunnelRefused(code: u16) {
+            description("proxy refused to establish a tunnel")
+            display("proxy refused to establish a tunnel: status {}", code)
+        }
+        /// Only `http` urls can be requested through `HttpProxy`
+        ProxyScheme {
+            description("url scheme is not supported by http proxy")
+        }
+        /// Response body can't be decompressed
+        DecompressionError {
+            description("error decompressing response body")
+        }
         Custom(err: Box<::std::error::Error + Send + Sync>) {
             description("custom error")
             cause(&**err)
@@ -110,6 +145,15 @@ quick_error! {
     }
 }
 
+impl From<chunked::Error> for ErrorEnum {
+    fn from(err: chunked::Error) -> ErrorEnum {
+        match err {
+            chunked::Error::ChunkSize(e) => ErrorEnum::ChunkSize(e),
+            chunked::Error::Trailers(e) => ErrorEnum::Trailers(e),
+        }
+    }
+}
+
 impl<T> From<SendError<T>> for ErrorEnum {
     fn from(_: SendError<T>) -> ErrorEnum {
         ErrorEnum::PoolError
@@ -0,0 +1,77 @@
+//! In-memory connections for tests
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures::{Future, Sink};
+use futures::future::{lazy, ok};
+use futures::sync::oneshot::Receiver;
+use tk_bufstream::MockData;
+use tokio_core::reactor::Core;
+
+use client::{Codec, Config, Error, Proto};
+use client::buffered::Response;
+
+
+/// A boxed connector, as accepted by `Pool` and `FollowRedirects`
+pub type Connector = Box<FnMut(&str) -> Box<Future<Item=MockData,
+                                                   Error=Error>>>;
+
+/// Keeps every connection made by the connector along with its authority
+#[derive(Clone)]
+pub struct Connections(Rc<RefCell<Vec<(String, MockData)>>>);
+
+impl Connections {
+    pub fn new() -> Connections {
+        Connections(Rc::new(RefCell::new(Vec::new())))
+    }
+    /// Returns a connector which records connections into this list
+    pub fn connector(&self) -> Connector {
+        let list = self.0.clone();
+        Box::new(move |authority: &str| {
+            let mock = MockData::new();
+            list.borrow_mut().push((authority.to_string(), mock.clone()));
+            Box::new(ok(mock)) as Box<Future<Item=_, Error=_>>
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+    pub fn authority(&self, idx: usize) -> String {
+        self.0.borrow()[idx].0.clone()
+    }
+    /// Everything written to the connection so far
+    pub fn output(&self, idx: usize) -> String {
+        String::from_utf8(self.0.borrow()[idx].1.output(..)).unwrap()
+    }
+    /// Data written to the connection since the last call
+    pub fn take_output(&self, idx: usize) -> String {
+        let mock = &self.0.borrow()[idx].1;
+        String::from_utf8(mock.get_output(mock.output(..).len())).unwrap()
+    }
+    pub fn respond(&self, idx: usize, data: &str) {
+        self.0.borrow()[idx].1.add_input(data);
+    }
+}
+
+/// Sends request with `codec` and returns response parsed from `input`
+///
+/// Response is received through `rx`, which is usually created together
+/// with `Buffered` codec (possibly wrapped).
+pub fn exchange<C, I>(codec: C, rx: Receiver<Result<Response, Error>>,
+    input: I)
+    -> Result<Response, Error>
+    where C: Codec<MockData>,
+          I: AsRef<[u8]>,
+{
+    let mut lp = Core::new().unwrap();
+    let mock = MockData::new();
+    let mut proto = Proto::new(mock.clone(), &lp.handle(),
+                               &Config::new().done());
+    lp.run(lazy(|| {
+        proto.start_send(codec)?;
+        proto.poll_complete()
+    })).unwrap();
+    mock.add_input(input);
+    lp.run(lazy(|| proto.poll_complete())).ok();
+    lp.run(rx).expect("response is sent")
+}
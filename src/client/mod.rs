@@ -6,14 +6,23 @@ mod encoder;
 mod errors;
 mod head;
 mod parser;
+mod pool;
 mod proto;
+mod proxy;
 mod recv_mode;
+mod tunnel;
 pub mod buffered;
+pub mod upload;
+pub mod decompress;
+#[cfg(test)] mod mock;
 
 pub use self::errors::Error;
 pub use self::client::{Client, Codec};
-pub use self::encoder::{Encoder, EncoderDone};
+pub use self::encoder::{Encoder, EncoderDone, WaitFlush};
 pub use self::proto::{Proto};
+pub use self::pool::{Pool};
+pub use self::tunnel::{Tunnel, FutureTunnel};
+pub use self::proxy::HttpProxy;
 
 use std::borrow::Cow;
 use std::time::Duration;
@@ -31,6 +40,7 @@ pub struct Config {
     keep_alive_timeout: Duration,
     safe_pipeline_timeout: Duration,
     max_request_timeout: Duration,
+    max_connections: usize,
 }
 
 /// A borrowed structure that represents response headers
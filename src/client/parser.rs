@@ -35,6 +35,8 @@ enum State {
     Body {
         mode: Mode,
         progress: BodyProgress,
+        /// Trailers are passed to the codec already
+        trailers: bool,
     },
 }
 
@@ -186,6 +188,7 @@ fn parse_headers<S: Io, C: Codec<S>>(
         State::Body {
             mode: mode.mode,
             progress: new_body(body, mode.mode)?,
+            trailers: false,
         },
         close,
     )))
@@ -215,7 +218,10 @@ impl<S: Io, C: Codec<S>> Parser<S, C> {
                 ref close_signal,
             } = self.state
         {
-            if io.read().map_err(ErrorEnum::Io)? == 0 {
+            // Next pipelined response may already be in the buffer
+            if io.read().map_err(ErrorEnum::Io)? == 0 &&
+                io.in_buf.len() == 0
+            {
                 if io.done() {
                     return Err(ErrorEnum::ResetOnResponseHeaders.into());
                 } else {
@@ -246,10 +252,15 @@ impl<S: Io, C: Codec<S>> Parser<S, C> {
         loop {
             match self.state {
                 Headers {..} => unreachable!(),
-                Body { ref mode, ref mut progress } => {
-                    progress.parse(&mut io).map_err(ErrorEnum::ChunkSize)?;
+                Body { ref mode, ref mut progress, ref mut trailers } => {
+                    progress.parse(&mut io).map_err(ErrorEnum::from)?;
                     let (bytes, done) = progress.check_buf(&io);
                     let operation = if done {
+                        if !*trailers {
+                            *trailers = true;
+                            self.codec.trailers_received(
+                                progress.trailers())?;
+                        }
                         Some(self.codec.data_received(
                             &io.in_buf[..bytes], true)?)
                     } else if io.done() {
@@ -0,0 +1,545 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+
+use futures::{Future, Async, AsyncSink, Sink, Stream, StartSend, Poll};
+use futures::future::IntoFuture;
+use futures::sync::mpsc::{channel, Sender, Receiver};
+use tokio_core::io::Io;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+
+use client::{Codec, Config, Error, EncoderDone, Proto};
+use client::errors::ErrorEnum;
+
+
+/// A connection pool for HTTP client
+///
+/// This is a `Sink` of boxed codecs, so it implements `Client` trait and
+/// you can use `fetch_url` with it. The destination of each request is
+/// determined by `Codec::authority()`.
+///
+/// Pool keeps idle keep-alive connections for every `host:port` pair and
+/// reuses them for subsequent requests, respecting `inflight_request_limit`
+/// of each connection. Connections that are closed by peer or failed are
+/// dropped and new ones are established on demand. The total number of
+/// connections (including ones being established) is limited by
+/// `Config::max_connections`, idle connections to other hosts are closed
+/// when there is a request that needs a connection but limit is reached.
+///
+/// Connections are run by a future spawned on the main loop, the `Pool`
+/// object itself is just a channel to that future, so it may be cloned
+/// freely. All connections are closed when all clones of the `Pool` are
+/// dropped and pending requests are done.
+pub struct Pool<S: Io, F> {
+    sender: Sender<Box<Codec<S, Future=F>>>,
+}
+
+struct Connection<S: Io, F>
+    where F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    proto: Proto<S, Box<Codec<S, Future=F>>>,
+    idle: bool,
+}
+
+struct Host<S: Io, F>
+    where F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    connections: Vec<Connection<S, F>>,
+    connecting: Vec<Box<Future<Item=S, Error=Error>>>,
+    queue: VecDeque<Box<Codec<S, Future=F>>>,
+}
+
+struct Driver<S: Io, F, C>
+    where F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    requests: Option<Receiver<Box<Codec<S, Future=F>>>>,
+    hosts: HashMap<String, Host<S, F>>,
+    connector: C,
+    config: Arc<Config>,
+    handle: Handle,
+}
+
+
+impl<S, F> Pool<S, F>
+    where S: Io + 'static,
+          F: Future<Item=EncoderDone<S>, Error=Error> + 'static,
+{
+    /// Create a new pool and spawn a future that runs connections
+    ///
+    /// The `connector` is called with a `host:port` string (as returned by
+    /// `Codec::authority()`) and must return a future that resolves to
+    /// a connected transport. This way pool may be used for TLS or
+    /// unix sockets, and resolving names is up to you.
+    pub fn new<C>(connector: C, cfg: &Arc<Config>, handle: &Handle)
+        -> Pool<S, F>
+        where C: FnMut(&str) -> Box<Future<Item=S, Error=Error>> + 'static,
+    {
+        let (tx, rx) = channel(cfg.max_connections);
+        handle.spawn(Driver {
+            requests: Some(rx),
+            hosts: HashMap::new(),
+            connector: connector,
+            config: cfg.clone(),
+            handle: handle.clone(),
+        });
+        Pool {
+            sender: tx,
+        }
+    }
+}
+
+impl<F> Pool<TcpStream, F>
+    where F: Future<Item=EncoderDone<TcpStream>, Error=Error> + 'static,
+{
+    /// A convenience method to create a pool of plain TCP connections
+    ///
+    /// Note: names are resolved using `ToSocketAddrs`, which blocks the
+    /// main loop. Use `Pool::new` with your own connector if this is
+    /// a problem.
+    pub fn connect_tcp(cfg: &Arc<Config>, handle: &Handle)
+        -> Pool<TcpStream, F>
+    {
+        let h = handle.clone();
+        Pool::new(move |authority: &str| connect_tcp(authority, &h),
+                  cfg, handle)
+    }
+}
+
+pub fn connect_tcp(authority: &str, handle: &Handle)
+    -> Box<Future<Item=TcpStream, Error=Error>>
+{
+    let addr = match authority.to_socket_addrs() {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => addr,
+            None => {
+                return Box::new(Err(ErrorEnum::InvalidUrl.into())
+                    .into_future());
+            }
+        },
+        Err(e) => {
+            return Box::new(Err(ErrorEnum::Io(e).into()).into_future());
+        }
+    };
+    Box::new(TcpStream::connect(&addr, handle)
+        .map_err(ErrorEnum::Io).map_err(Error::from))
+}
+
+impl<S: Io, F> Clone for Pool<S, F> {
+    fn clone(&self) -> Pool<S, F> {
+        Pool {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S: Io, F> Sink for Pool<S, F> {
+    type SinkItem = Box<Codec<S, Future=F>>;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Self::SinkItem)
+        -> StartSend<Self::SinkItem, Self::SinkError>
+    {
+        self.sender.start_send(item)
+            .map_err(|e| ErrorEnum::from(e).into())
+    }
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.sender.poll_complete()
+            .map_err(|e| ErrorEnum::from(e).into())
+    }
+}
+
+impl<S: Io, F> Host<S, F>
+    where F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    fn new() -> Host<S, F> {
+        Host {
+            connections: Vec::new(),
+            connecting: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+    fn is_idle(&self) -> bool {
+        self.queue.len() == 0 && self.connecting.len() == 0 &&
+            self.connections.iter().all(|c| c.idle)
+    }
+    fn poll_connecting(&mut self, authority: &str,
+        config: &Arc<Config>, handle: &Handle)
+    {
+        let mut failed = false;
+        let mut i = 0;
+        while i < self.connecting.len() {
+            match self.connecting[i].poll() {
+                Ok(Async::Ready(sock)) => {
+                    self.connecting.swap_remove(i);
+                    self.connections.push(Connection {
+                        proto: Proto::new(sock, handle, config),
+                        idle: true,
+                    });
+                }
+                Ok(Async::NotReady) => i += 1,
+                Err(e) => {
+                    self.connecting.swap_remove(i);
+                    warn!("Error connecting to {}: {}", authority, e);
+                    failed = true;
+                }
+            }
+        }
+        if failed && self.connections.len() == 0 &&
+            self.connecting.len() == 0 && self.queue.len() > 0
+        {
+            // Dropping requests notifies the requesters, we don't retry
+            // here, to avoid reconnecting in a tight loop
+            warn!("Dropping {} requests to {}",
+                self.queue.len(), authority);
+            self.queue.clear();
+        }
+    }
+    fn poll_connections(&mut self, authority: &str) {
+        let mut i = 0;
+        while i < self.connections.len() {
+            match self.connections[i].proto.poll_complete() {
+                Ok(Async::Ready(())) => {
+                    self.connections[i].idle = true;
+                    i += 1;
+                }
+                Ok(Async::NotReady) => {
+                    self.connections[i].idle = false;
+                    i += 1;
+                }
+                Err(e) => {
+                    // Requests in flight are dropped with the connection
+                    debug!("Connection to {} closed: {}", authority, e);
+                    self.connections.swap_remove(i);
+                }
+            }
+        }
+    }
+    fn dispatch(&mut self, authority: &str) {
+        'requests: while let Some(mut codec) = self.queue.pop_front() {
+            let mut i = 0;
+            while i < self.connections.len() {
+                // Check connection before sending, so the request is not
+                // lost if connection is already dead
+                if let Err(e) = self.connections[i].proto.poll_complete() {
+                    debug!("Connection to {} closed: {}", authority, e);
+                    self.connections.swap_remove(i);
+                    continue;
+                }
+                match self.connections[i].proto.start_send(codec) {
+                    Ok(AsyncSink::Ready) => {
+                        self.connections[i].idle = false;
+                        continue 'requests;
+                    }
+                    Ok(AsyncSink::NotReady(c)) => {
+                        codec = c;
+                        i += 1;
+                    }
+                    Err(e) => {
+                        // Protocol only fails here after request is
+                        // accepted, so it's canceled along with other
+                        // requests in flight on this connection
+                        debug!("Connection to {} failed: {}", authority, e);
+                        self.connections.swap_remove(i);
+                        continue 'requests;
+                    }
+                }
+            }
+            self.queue.push_front(codec);
+            break;
+        }
+    }
+}
+
+impl<S: Io, F, C> Driver<S, F, C>
+    where F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    fn total_connections(&self) -> usize {
+        self.hosts.values()
+            .map(|h| h.connections.len() + h.connecting.len())
+            .sum()
+    }
+    fn receive(&mut self) {
+        loop {
+            // Backpressure: don't accept more requests than we could
+            // send to the new connections
+            let queued: usize = self.hosts.values()
+                .map(|h| h.queue.len()).sum();
+            if queued >= self.config.max_connections {
+                return;
+            }
+            let item = match self.requests.as_mut() {
+                Some(requests) => requests.poll(),
+                None => return,
+            };
+            match item {
+                Ok(Async::Ready(Some(codec))) => {
+                    match codec.authority() {
+                        Some(authority) => {
+                            self.hosts.entry(authority)
+                                .or_insert_with(Host::new)
+                                .queue.push_back(codec);
+                        }
+                        None => {
+                            warn!("Request without authority can't be \
+                                sent through a connection pool");
+                        }
+                    }
+                }
+                Ok(Async::Ready(None)) => {
+                    self.requests = None;
+                    return;
+                }
+                Ok(Async::NotReady) => return,
+                Err(()) => unreachable!(),
+            }
+        }
+    }
+    /// Closes one idle connection to a host without pending requests
+    ///
+    /// Returns `true` if some connection is closed
+    fn evict_idle(&mut self) -> bool {
+        for (authority, host) in self.hosts.iter_mut() {
+            if host.queue.len() > 0 {
+                continue;
+            }
+            if let Some(idx) = host.connections.iter().position(|c| c.idle) {
+                debug!("Closing idle connection to {}", authority);
+                host.connections.swap_remove(idx);
+                return true;
+            }
+        }
+        return false;
+    }
+}
+
+impl<S, F, C> Future for Driver<S, F, C>
+    where S: Io,
+          F: Future<Item=EncoderDone<S>, Error=Error>,
+          C: FnMut(&str) -> Box<Future<Item=S, Error=Error>>,
+{
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            self.receive();
+            let limit = self.config.inflight_request_limit;
+            let max = self.config.max_connections;
+            let mut total = self.total_connections();
+            let mut starving = false;
+            let mut repeat = false;
+            for (authority, host) in self.hosts.iter_mut() {
+                host.poll_connecting(authority, &self.config, &self.handle);
+                host.poll_connections(authority);
+                host.dispatch(authority);
+                // flush requests that were just dispatched
+                host.poll_connections(authority);
+                while host.queue.len() > host.connecting.len() * limit {
+                    if total >= max {
+                        if host.connecting.len() == 0 {
+                            starving = true;
+                        }
+                        break;
+                    }
+                    debug!("Connecting to {}", authority);
+                    host.connecting.push((self.connector)(&authority[..]));
+                    total += 1;
+                    // new futures must be polled to get notifications
+                    repeat = true;
+                }
+            }
+            if starving && self.evict_idle() {
+                repeat = true;
+            }
+            let empty: Vec<_> = self.hosts.iter()
+                .filter(|&(_, h)| h.queue.len() == 0 &&
+                    h.connecting.len() == 0 && h.connections.len() == 0)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in empty {
+                self.hosts.remove(&key);
+            }
+            if !repeat {
+                break;
+            }
+        }
+        if self.requests.is_none() && self.hosts.values().all(|h| h.is_idle())
+        {
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures::{Future, Async};
+    use futures::future::{FutureResult, lazy};
+    use futures::sync::oneshot::Receiver;
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use client::{Codec, Config, Error, EncoderDone};
+    use client::buffered::{Buffered, Response};
+    use client::mock::{Connections, Connector};
+    use super::{Driver, Host};
+
+    type ResponseRx = Receiver<Result<Response, Error>>;
+
+    struct Fixture {
+        lp: Core,
+        driver: Driver<MockData,
+            FutureResult<EncoderDone<MockData>, Error>, Connector>,
+        connections: Connections,
+    }
+
+    const RESPONSE: &'static str = "HTTP/1.1 200 OK\r\n\
+                                    Content-Length: 2\r\n\r\nok";
+
+    impl Fixture {
+        fn new(cfg: &Arc<Config>) -> Fixture {
+            let lp = Core::new().unwrap();
+            let connections = Connections::new();
+            Fixture {
+                driver: Driver {
+                    requests: None,
+                    hosts: Default::default(),
+                    connector: connections.connector(),
+                    config: cfg.clone(),
+                    handle: lp.handle(),
+                },
+                lp: lp,
+                connections: connections,
+            }
+        }
+        fn request(&mut self, url: &str) -> ResponseRx {
+            let (codec, rx) = Buffered::get(url.parse().unwrap());
+            let authority = Codec::<MockData>::authority(&codec).unwrap();
+            self.driver.hosts.entry(authority)
+                .or_insert_with(Host::new)
+                .queue.push_back(Box::new(codec));
+            rx
+        }
+        fn poll(&mut self) {
+            let driver = &mut self.driver;
+            self.lp.run(lazy(|| driver.poll())).ok();
+        }
+        fn is_done(&mut self, rx: &mut ResponseRx) -> bool {
+            match self.lp.run(lazy(|| Ok::<_, ()>(rx.poll()))).unwrap() {
+                Ok(Async::Ready(Ok(response))) => {
+                    assert_eq!(response.body(), b"ok");
+                    true
+                }
+                Ok(Async::NotReady) => false,
+                _ => panic!("request failed"),
+            }
+        }
+        fn connections(&self) -> usize {
+            self.connections.len()
+        }
+        fn output(&self, idx: usize) -> String {
+            self.connections.output(idx)
+        }
+        fn respond(&self, idx: usize) {
+            self.connections.respond(idx, RESPONSE);
+        }
+    }
+
+    #[test]
+    fn reuse_idle() {
+        let mut f = Fixture::new(&Config::new().done());
+        let mut a = f.request("http://example.com/a");
+        f.poll();
+        assert_eq!(f.connections(), 1);
+        assert!(f.output(0).starts_with("GET /a HTTP/1.1\r\n"));
+        assert!(!f.is_done(&mut a));
+        f.respond(0);
+        f.poll();
+        assert!(f.is_done(&mut a));
+
+        let mut b = f.request("http://example.com/b");
+        f.poll();
+        assert_eq!(f.connections(), 1);
+        assert!(f.output(0).contains("GET /b HTTP/1.1\r\n"));
+        f.respond(0);
+        f.poll();
+        assert!(f.is_done(&mut b));
+    }
+
+    #[test]
+    fn inflight_limit() {
+        let mut f = Fixture::new(&Config::new()
+            .inflight_request_limit(1).done());
+        let mut a = f.request("http://example.com/a");
+        let mut b = f.request("http://example.com/b");
+        f.poll();
+        assert_eq!(f.connections(), 2);
+        assert!(f.output(0).starts_with("GET /a HTTP/1.1\r\n"));
+        assert!(!f.output(0).contains("GET /b"));
+        assert!(f.output(1).starts_with("GET /b HTTP/1.1\r\n"));
+        f.respond(0);
+        f.respond(1);
+        f.poll();
+        assert!(f.is_done(&mut a));
+        assert!(f.is_done(&mut b));
+    }
+
+    #[test]
+    fn pipelining() {
+        let mut f = Fixture::new(&Config::new()
+            .inflight_request_limit(2).done());
+        let mut a = f.request("http://example.com/a");
+        let mut b = f.request("http://example.com/b");
+        f.poll();
+        assert!(f.output(0).starts_with("GET /a HTTP/1.1\r\n"));
+        assert!(f.output(0).contains("GET /b HTTP/1.1\r\n"));
+        f.respond(0);
+        f.respond(0);
+        f.poll();
+        assert!(f.is_done(&mut a));
+        assert!(f.is_done(&mut b));
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut f = Fixture::new(&Config::new().done());
+        let mut a = f.request("http://example.com/a");
+        f.poll();
+        f.respond(0);
+        f.poll();
+        assert!(f.is_done(&mut a));
+        // response without request means connection is broken
+        f.respond(0);
+        let mut b = f.request("http://example.com/b");
+        f.poll();
+        assert_eq!(f.connections(), 2);
+        assert!(!f.output(0).contains("GET /b"));
+        assert!(f.output(1).starts_with("GET /b HTTP/1.1\r\n"));
+        f.respond(1);
+        f.poll();
+        assert!(f.is_done(&mut b));
+    }
+
+    #[test]
+    fn max_connections() {
+        let mut f = Fixture::new(&Config::new().max_connections(1).done());
+        let mut a = f.request("http://one.example.com/a");
+        f.poll();
+        let mut b = f.request("http://two.example.com/b");
+        f.poll();
+        // limit is reached and connection is busy
+        assert_eq!(f.connections(), 1);
+        f.respond(0);
+        f.poll();
+        assert!(f.is_done(&mut a));
+        // idle connection to the first host is evicted
+        assert_eq!(f.connections(), 2);
+        assert_eq!(f.connections.authority(1), "two.example.com:80");
+        assert!(f.output(1).starts_with("GET /b HTTP/1.1\r\n"));
+        assert!(!f.driver.hosts.contains_key("one.example.com:80"));
+        f.respond(1);
+        f.poll();
+        assert!(f.is_done(&mut b));
+    }
+}
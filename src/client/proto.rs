@@ -209,7 +209,9 @@ impl<S: Io, C: Codec<S>> Sink for PureProto<S, C> {
                 }
                 if self.close.load(Ordering::SeqCst) {
                     // TODO(tailhook) maybe shutdown?
-                    io.flush().map_err(ErrorEnum::Io)?;
+                    // Flush error is reported by `poll_complete`, so that
+                    // request is returned to the caller rather than lost
+                    io.flush().ok();
                     (AsyncSink::NotReady(item), OutState::Idle(io, time))
                 } else {
                     let mut limit = self.config.inflight_request_limit;
@@ -0,0 +1,157 @@
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+use url::{Url, Position};
+
+use base64;
+
+
+/// Headers that are meaningful for a single connection only
+///
+/// This is the list from RFC 7230 (section 6.1) plus `Proxy-Connection`.
+/// `Connection` is not here, because it's interpreted by the encoder and
+/// applies to the connection to the proxy as well. `Proxy-Authorization`
+/// is checked separately, because it's addressed to the proxy.
+const HOP_BY_HOP: &'static [&'static str] = &[
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Returns names of the headers listed in `Connection` header
+///
+/// These headers are hop-by-hop too, so they are not sent to the proxy.
+pub fn connection_options(headers: &[(String, Vec<u8>)]) -> Vec<&str> {
+    headers.iter()
+        .filter(|&&(ref name, _)| name.eq_ignore_ascii_case("Connection"))
+        .filter_map(|&(_, ref value)| from_utf8(value).ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim())
+        .filter(|name| name.len() > 0)
+        .collect()
+}
+
+/// Settings of the plain HTTP forward proxy
+///
+/// Requests are sent to the proxy with the absolute url as a request
+/// target (i.e. `GET http://example.com/path HTTP/1.1`). This only works
+/// for `http` urls, requests to other urls fail, use `Tunnel` for
+/// `https`.
+///
+/// ```rust,ignore
+/// let mut proxy = HttpProxy::new("proxy.local:3128");
+/// proxy.basic_auth("user", "password");
+/// let (mut codec, receiver) = Buffered::get(url);
+/// codec.http_proxy(&proxy);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpProxy {
+    authority: String,
+    authorization: Option<String>,
+}
+
+impl HttpProxy {
+    /// Create proxy settings for the proxy at `authority` (`host:port`)
+    ///
+    /// # Panics
+    ///
+    /// When authority contains whitespace or control characters.
+    pub fn new(authority: &str) -> HttpProxy {
+        assert!(authority.len() > 0 &&
+                !authority.bytes().any(|b| b <= b' ' || b == 0x7F),
+                "invalid authority {:?}", authority);
+        HttpProxy {
+            authority: authority.to_string(),
+            authorization: None,
+        }
+    }
+    /// Set `Proxy-Authorization` header sent with each request
+    ///
+    /// # Panics
+    ///
+    /// When value contains newlines.
+    pub fn proxy_authorization(&mut self, value: &str) -> &mut Self {
+        assert!(!value.bytes().any(|b| b == b'\r' || b == b'\n'),
+                "invalid header value {:?}", value);
+        self.authorization = Some(value.to_string());
+        self
+    }
+    /// Set `Proxy-Authorization` header for the basic authentication
+    pub fn basic_auth(&mut self, user: &str, password: &str) -> &mut Self {
+        let credentials = base64::encode(
+            format!("{}:{}", user, password).as_bytes());
+        self.authorization = Some(format!("Basic {}", credentials));
+        self
+    }
+    /// Returns `host:port` of the proxy, this is where to connect to
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+    /// Returns value of `Proxy-Authorization` header if set
+    pub fn authorization(&self) -> Option<&str> {
+        self.authorization.as_ref().map(|x| &x[..])
+    }
+    /// Returns request target in the absolute form for the url
+    ///
+    /// Credentials and fragment of the url are not included.
+    pub fn request_target(&self, url: &Url) -> String {
+        format!("{}://{}{}", url.scheme(),
+            &url[Position::BeforeHost..Position::AfterPort],
+            &url[Position::BeforePath..Position::AfterQuery])
+    }
+    /// Returns `true` if the header must not be forwarded to the proxy
+    ///
+    /// These are hop-by-hop headers, and `Proxy-Authorization` if it's
+    /// set in these settings (so it's not sent twice).
+    pub fn skip_header(&self, name: &str) -> bool {
+        HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name)) ||
+            self.authorization.is_some() &&
+            name.eq_ignore_ascii_case("Proxy-Authorization")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HttpProxy, connection_options};
+
+    #[test]
+    fn request_target() {
+        let proxy = HttpProxy::new("proxy.local:3128");
+        let url = "http://user:pw@example.com:8080/a/b?x=1#frag"
+            .parse().unwrap();
+        assert_eq!(proxy.request_target(&url),
+                   "http://example.com:8080/a/b?x=1");
+        let url = "http://example.com".parse().unwrap();
+        assert_eq!(proxy.request_target(&url), "http://example.com/");
+    }
+
+    #[test]
+    fn skip_header() {
+        let mut proxy = HttpProxy::new("proxy.local:3128");
+        assert!(proxy.skip_header("keep-alive"));
+        assert!(proxy.skip_header("Upgrade"));
+        assert!(proxy.skip_header("Proxy-Authenticate"));
+        assert!(proxy.skip_header("transfer-encoding"));
+        assert!(!proxy.skip_header("Connection"));
+        assert!(!proxy.skip_header("Proxy-Authorization"));
+        proxy.basic_auth("Aladdin", "open sesame");
+        assert!(proxy.skip_header("proxy-authorization"));
+        assert_eq!(proxy.authorization(),
+                   Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+    }
+
+    #[test]
+    fn connection() {
+        let headers = vec![
+            ("Connection".to_string(), b"close, X-Hop ,".to_vec()),
+            ("X-Hop".to_string(), b"1".to_vec()),
+            ("connection".to_string(), b"x-other".to_vec()),
+        ];
+        assert_eq!(connection_options(&headers),
+                   vec!["close", "X-Hop", "x-other"]);
+    }
+}
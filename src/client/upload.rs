@@ -0,0 +1,399 @@
+//! A codec that streams request body and buffers the response
+//!
+//! This is useful for uploading large files, where the body doesn't fit
+//! memory. Response is fully buffered just like in `buffered` module.
+//!
+use std::io::{self, Read};
+
+use url::{Url, Position};
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::oneshot::Receiver;
+use tokio_core::io::Io;
+
+use enums::Method;
+use base_serializer::{HeaderError, check_header};
+use client::{Error, Codec, Encoder, EncoderDone, Head, RecvMode, WaitFlush};
+use client::buffered::{Buffered, Response};
+use client::errors::ErrorEnum;
+use client::encoder::request_head;
+
+
+/// Default number of bytes in the output buffer, when we stop reading
+/// the body stream
+const WATERMARK: usize = 65536;
+
+
+/// A codec that writes request body from a `Stream`
+///
+/// Next chunk is pulled from the stream only when output buffer is flushed
+/// below the watermark, so the whole body is never held in memory.
+pub struct Upload<B> {
+    method: Method,
+    url: Url,
+    headers: Vec<(String, Vec<u8>)>,
+    length: Option<u64>,
+    body: Option<B>,
+    watermark: usize,
+    response: Buffered,
+}
+
+/// A future returned from `Upload::start_write`
+pub struct WriteBody<S: Io, B> {
+    state: State<S>,
+    body: B,
+    bytes_left: Option<u64>,
+    watermark: usize,
+}
+
+enum State<S: Io> {
+    Flush(WaitFlush<S>),
+    Read(Encoder<S>),
+    Void,
+}
+
+/// A stream of chunks read from `std::io::Read`
+///
+/// Note: reads are blocking, so it's only good for regular files and
+/// similar things.
+pub struct ReadChunks<R> {
+    reader: R,
+    chunk_size: usize,
+}
+
+impl<B: Stream<Item=Vec<u8>, Error=Error>> Upload<B> {
+    /// Create a request with the body from the stream
+    ///
+    /// If `length` is specified, it's sent as `Content-Length` and stream
+    /// must yield exactly this number of bytes. Otherwise body is sent
+    /// using chunked encoding.
+    pub fn new(method: Method, url: Url, body: B, length: Option<u64>)
+        -> (Upload<B>, Receiver<Result<Response, Error>>)
+    {
+        let (response, rx) = Buffered::get(url.clone());
+        (Upload {
+                method: method,
+                url: url,
+                headers: Vec::new(),
+                length: length,
+                body: Some(body),
+                watermark: WATERMARK,
+                response: response,
+            },
+         rx)
+    }
+    /// Send a POST request with the body from the stream
+    pub fn post(url: Url, body: B, length: Option<u64>)
+        -> (Upload<B>, Receiver<Result<Response, Error>>)
+    {
+        Upload::new(Method::Post, url, body, length)
+    }
+    /// Send a PUT request with the body from the stream
+    pub fn put(url: Url, body: B, length: Option<u64>)
+        -> (Upload<B>, Receiver<Result<Response, Error>>)
+    {
+        Upload::new(Method::Put, url, body, length)
+    }
+    /// Add a header to the request
+    ///
+    /// `Host` is added automatically, `Content-Length` and
+    /// `Transfer-Encoding` are derived from the `length`, so adding them
+    /// here is an error, as well as adding newlines in name or value.
+    pub fn add_header<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        check_header(name, value.as_ref())?;
+        self.headers.push((name.to_string(), value.as_ref().to_vec()));
+        Ok(())
+    }
+    /// Set the size of output buffer when we stop reading the stream
+    ///
+    /// Default is 64KiB.
+    pub fn watermark(&mut self, bytes: usize) {
+        self.watermark = bytes;
+    }
+    /// Set max response length for this request
+    pub fn max_response_length(&mut self, value: usize) {
+        self.response.max_response_length(value);
+    }
+}
+
+impl<R: Read> ReadChunks<R> {
+    /// Create a stream that reads chunks of at most `chunk_size` bytes
+    pub fn new(reader: R, chunk_size: usize) -> ReadChunks<R> {
+        assert!(chunk_size > 0);
+        ReadChunks {
+            reader: reader,
+            chunk_size: chunk_size,
+        }
+    }
+}
+
+impl<R: Read> Stream for ReadChunks<R> {
+    type Item = Vec<u8>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        let mut buf = vec![0; self.chunk_size];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(Async::Ready(None)),
+                Ok(bytes) => {
+                    buf.truncate(bytes);
+                    return Ok(Async::Ready(Some(buf)));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ErrorEnum::Io(e).into()),
+            }
+        }
+    }
+}
+
+impl<S, B> Codec<S> for Upload<B>
+    where S: Io,
+          B: Stream<Item=Vec<u8>, Error=Error>,
+{
+    type Future = WriteBody<S, B>;
+    fn start_write(&mut self, mut e: Encoder<S>) -> Self::Future {
+        request_head(&mut e, self.method.clone(),
+            &self.url[Position::BeforePath..Position::AfterQuery],
+            &self.url);
+        for &(ref name, ref value) in &self.headers {
+            e.add_header(name, value).unwrap();
+        }
+        match self.length {
+            Some(n) => e.add_length(n).unwrap(),
+            None => e.add_chunked().unwrap(),
+        }
+        e.done_headers().unwrap();
+        WriteBody {
+            state: State::Read(e),
+            body: self.body.take().expect("request is written only once"),
+            bytes_left: self.length,
+            watermark: self.watermark,
+        }
+    }
+    fn headers_received(&mut self, headers: &Head) -> Result<RecvMode, Error> {
+        Codec::<S>::headers_received(&mut self.response, headers)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        Codec::<S>::data_received(&mut self.response, data, end)
+    }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        Codec::<S>::trailers_received(&mut self.response, trailers)
+    }
+    fn authority(&self) -> Option<String> {
+        Codec::<S>::authority(&self.response)
+    }
+}
+
+impl<S, B> Future for WriteBody<S, B>
+    where S: Io,
+          B: Stream<Item=Vec<u8>, Error=Error>,
+{
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        use std::mem::replace;
+        use self::State::*;
+        loop {
+            let next = match replace(&mut self.state, Void) {
+                Flush(mut f) => match f.poll().map_err(ErrorEnum::Io)? {
+                    Async::Ready(e) => Read(e),
+                    Async::NotReady => {
+                        self.state = Flush(f);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Read(mut e) => match self.body.poll()? {
+                    Async::Ready(Some(chunk)) => {
+                        if let Some(ref mut left) = self.bytes_left {
+                            if chunk.len() as u64 > *left {
+                                return Err(
+                                    ErrorEnum::RequestBodyLength.into());
+                            }
+                            *left -= chunk.len() as u64;
+                        }
+                        e.write_body(&chunk);
+                        Flush(e.wait_flush(self.watermark))
+                    }
+                    Async::Ready(None) => {
+                        if self.bytes_left.unwrap_or(0) != 0 {
+                            return Err(ErrorEnum::RequestBodyLength.into());
+                        }
+                        return Ok(Async::Ready(e.done()));
+                    }
+                    Async::NotReady => {
+                        self.state = Read(e);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Void => unreachable!(),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, AtomicBool};
+
+    use futures::{Future, Async, Poll, Stream};
+    use tk_bufstream::{IoBuf, MockData};
+    use tokio_core::io::Io;
+
+    use client::{Codec, Error};
+    use client::encoder::{self, get_inner};
+    use super::{Upload, WriteBody};
+
+    /// A transport which may be switched to not accept writes
+    #[derive(Clone)]
+    struct Blocking {
+        mock: MockData,
+        blocked: Rc<Cell<bool>>,
+    }
+
+    /// A body stream which counts how many chunks are pulled
+    struct Chunks {
+        chunks: VecDeque<&'static str>,
+        pulled: Rc<Cell<usize>>,
+    }
+
+    impl Read for Blocking {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.mock.read(buf)
+        }
+    }
+
+    impl Write for Blocking {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocked.get() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.mock.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Io for Blocking {}
+
+    impl Stream for Chunks {
+        type Item = Vec<u8>;
+        type Error = Error;
+        fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+            self.pulled.set(self.pulled.get() + 1);
+            Ok(Async::Ready(self.chunks.pop_front()
+                .map(|x| x.as_bytes().to_vec())))
+        }
+    }
+
+    fn start(chunks: &[&'static str], length: Option<u64>, watermark: usize)
+        -> (Blocking, Rc<Cell<usize>>, WriteBody<Blocking, Chunks>)
+    {
+        let io = Blocking {
+            mock: MockData::new(),
+            blocked: Rc::new(Cell::new(false)),
+        };
+        let pulled = Rc::new(Cell::new(0));
+        let body = Chunks {
+            chunks: chunks.iter().cloned().collect(),
+            pulled: pulled.clone(),
+        };
+        let (mut upload, _) = Upload::post(
+            "http://example.com/upload".parse().unwrap(), body, length);
+        upload.watermark(watermark);
+        let (output, _) = IoBuf::new(io.clone()).split();
+        let e = encoder::new(output,
+            Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        let future = Codec::<Blocking>::start_write(&mut upload, e);
+        (io, pulled, future)
+    }
+
+    fn finish(mut future: WriteBody<Blocking, Chunks>) -> Result<(), Error> {
+        match future.poll()? {
+            Async::Ready(done) => {
+                get_inner(done).flush().unwrap();
+                Ok(())
+            }
+            Async::NotReady => panic!("body is not written"),
+        }
+    }
+
+    #[test]
+    fn body_length_headers() {
+        let (mut upload, _) = Upload::post(
+            "http://example.com/upload".parse().unwrap(),
+            Chunks { chunks: VecDeque::new(), pulled: Rc::new(Cell::new(0)) },
+            None);
+        assert!(upload.add_header("Content-Length", "10").is_err());
+        assert!(upload.add_header("transfer-encoding", "chunked").is_err());
+        assert!(upload.add_header("X-Test", "a\r\nb").is_err());
+        assert!(upload.add_header("Content-Type", "text/plain").is_ok());
+    }
+
+    #[test]
+    fn watermark() {
+        let (io, pulled, mut future) = start(&["hello", "world"], None, 0);
+        io.blocked.set(true);
+        assert!(matches!(future.poll(), Ok(Async::NotReady)));
+        assert_eq!(pulled.get(), 1);
+        // stream is not polled while buffer is above the watermark
+        assert!(matches!(future.poll(), Ok(Async::NotReady)));
+        assert_eq!(pulled.get(), 1);
+        assert_eq!(io.mock.output(..).len(), 0);
+        io.blocked.set(false);
+        finish(future).unwrap();
+        assert_eq!(pulled.get(), 3);
+        let output = String::from_utf8(io.mock.output(..)).unwrap();
+        assert!(output.ends_with(
+            "5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn chunked() {
+        let (io, _, future) = start(&["hello", " world"], None, 65536);
+        finish(future).unwrap();
+        assert_eq!(String::from_utf8(io.mock.output(..)).unwrap(),
+            "POST /upload HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n\
+             6\r\n world\r\n\
+             0\r\n\r\n");
+    }
+
+    #[test]
+    fn fixed_length() {
+        let (io, _, future) = start(&["hello", " world"], Some(11), 65536);
+        finish(future).unwrap();
+        assert_eq!(String::from_utf8(io.mock.output(..)).unwrap(),
+            "POST /upload HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Content-Length: 11\r\n\r\n\
+             hello world");
+    }
+
+    #[test]
+    fn too_short() {
+        let (_, _, future) = start(&["hello"], Some(11), 65536);
+        let err = finish(future).unwrap_err();
+        assert_eq!(format!("{:?}", err), "RequestBodyLength");
+    }
+
+    #[test]
+    fn too_long() {
+        let (_, _, future) = start(&["hello", " world!"], Some(11), 65536);
+        let err = finish(future).unwrap_err();
+        assert_eq!(format!("{:?}", err), "RequestBodyLength");
+    }
+}
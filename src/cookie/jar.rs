@@ -0,0 +1,263 @@
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::str::from_utf8;
+use std::time::{Duration, SystemTime};
+
+use url::{Url, Host};
+
+use date;
+
+
+/// Maximum lifetime of a cookie set by `Max-Age`, 400 days as suggested
+/// by the RFC 6265 update
+const MAX_AGE: u64 = 400 * 86400;
+
+
+#[derive(Debug, Clone)]
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    expires: Option<SystemTime>,
+}
+
+/// Client-side storage of cookies
+///
+/// Cookies are stored from `Set-Cookie` headers of responses and are
+/// sent back to requests according to domain, path and `Secure` rules
+/// of RFC 6265. Public suffix list is not checked, so don't use a single
+/// jar for requests to untrusted sites.
+///
+/// Use `Buffered::cookie_jar()` to attach jar to buffered requests.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain || host.len() > domain.len() && host.ends_with(domain)
+        && host[..host.len() - domain.len()].ends_with('.')
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path || path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/')
+            || path[cookie_path.len()..].starts_with('/'))
+}
+
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(idx) => path[..idx].to_string(),
+    }
+}
+
+impl CookieJar {
+    /// Create an empty cookie jar
+    pub fn new() -> CookieJar {
+        CookieJar {
+            cookies: Vec::new(),
+        }
+    }
+    /// Store cookies from all `Set-Cookie` headers of the response
+    pub fn store(&mut self, url: &Url, headers: &[(String, Vec<u8>)]) {
+        for &(ref name, ref value) in headers {
+            if name.eq_ignore_ascii_case("Set-Cookie") {
+                if let Ok(value) = from_utf8(value) {
+                    self.set_cookie(url, value);
+                }
+            }
+        }
+    }
+    /// Store a cookie from the value of `Set-Cookie` header
+    ///
+    /// Invalid cookies and ones with domain not matching the url are
+    /// ignored.
+    pub fn set_cookie(&mut self, url: &Url, header: &str) {
+        let host = match url.host() {
+            Some(Host::Domain(host)) => host.to_ascii_lowercase(),
+            Some(_) => url.host_str().unwrap().to_string(),
+            None => return,
+        };
+        let is_ip = !matches!(url.host(), Some(Host::Domain(_)));
+        let mut parts = header.split(';');
+        let (name, value) = {
+            let mut pair = parts.next().unwrap().splitn(2, '=');
+            let name = pair.next().unwrap().trim();
+            match pair.next() {
+                Some(value) if name.len() > 0 => (name, value.trim()),
+                _ => return,
+            }
+        };
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let mut pair = attr.splitn(2, '=');
+            let key = pair.next().unwrap().trim();
+            let value = pair.next().unwrap_or("").trim();
+            if key.eq_ignore_ascii_case("Expires") {
+                if let Some(time) = date::parse(value) {
+                    cookie.expires = Some(time);
+                }
+            } else if key.eq_ignore_ascii_case("Max-Age") {
+                if let Ok(secs) = value.parse::<i64>() {
+                    max_age = Some(secs);
+                }
+            } else if key.eq_ignore_ascii_case("Domain") {
+                let domain = value.trim_left_matches('.')
+                    .to_ascii_lowercase();
+                if domain.len() > 0 {
+                    if is_ip && domain != host ||
+                       !domain_match(&host, &domain)
+                    {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+            } else if key.eq_ignore_ascii_case("Path") {
+                if value.starts_with('/') {
+                    cookie.path = value.to_string();
+                }
+            } else if key.eq_ignore_ascii_case("Secure") {
+                cookie.secure = true;
+            }
+        }
+        let now = SystemTime::now();
+        match max_age {
+            Some(secs) if secs <= 0 => cookie.expires = Some(now),
+            Some(secs) => {
+                let secs = min(secs as u64, MAX_AGE);
+                cookie.expires = Some(now + Duration::from_secs(secs));
+            }
+            None => {}
+        }
+        self.cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain
+              && c.path == cookie.path)
+            && c.expires.map(|t| t > now).unwrap_or(true)
+        });
+        if cookie.expires.map(|t| t > now).unwrap_or(true) {
+            self.cookies.push(cookie);
+        }
+    }
+    /// Returns the value of `Cookie` header for the request to the url
+    ///
+    /// Returns `None` if there are no matching cookies.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return None,
+        };
+        let now = SystemTime::now();
+        let mut matching = self.cookies.iter().filter(|c| {
+            (if c.host_only { host == c.domain }
+             else { domain_match(&host, &c.domain) })
+            && path_match(url.path(), &c.path)
+            && (!c.secure || url.scheme() == "https")
+            && c.expires.map(|t| t > now).unwrap_or(true)
+        }).collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        // Cookies with longer paths go first (the sort is stable, so
+        // otherwise cookies go in order of creation)
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        let mut result = String::new();
+        for cookie in matching {
+            if !result.is_empty() {
+                result.push_str("; ");
+            }
+            result.push_str(&cookie.name);
+            result.push('=');
+            result.push_str(&cookie.value);
+        }
+        Some(result)
+    }
+    /// Remove all cookies
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use super::CookieJar;
+
+    fn url(s: &str) -> Url {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn host_only() {
+        let mut jar = CookieJar::new();
+        jar.set_cookie(&url("http://example.com/a/b"), "x=1");
+        assert_eq!(jar.header(&url("http://example.com/a/c")),
+                   Some("x=1".to_string()));
+        assert_eq!(jar.header(&url("http://example.com/b")), None);
+        assert_eq!(jar.header(&url("http://www.example.com/a/c")), None);
+    }
+
+    #[test]
+    fn domain() {
+        let mut jar = CookieJar::new();
+        jar.set_cookie(&url("http://www.example.com/"),
+                       "x=1; Domain=.Example.com; Path=/");
+        jar.set_cookie(&url("http://www.example.com/"),
+                       "y=2; Domain=other.com; Path=/");
+        assert_eq!(jar.header(&url("http://api.example.com/a")),
+                   Some("x=1".to_string()));
+        assert_eq!(jar.header(&url("http://example.com/")),
+                   Some("x=1".to_string()));
+        assert_eq!(jar.header(&url("http://badexample.com/")), None);
+        assert_eq!(jar.header(&url("http://other.com/")), None);
+    }
+
+    #[test]
+    fn path_and_secure() {
+        let mut jar = CookieJar::new();
+        let base = url("https://example.com/");
+        jar.set_cookie(&base, "a=1; Path=/");
+        jar.set_cookie(&base, "b=2; Path=/docs; Secure");
+        assert_eq!(jar.header(&url("https://example.com/docs/x")),
+                   Some("b=2; a=1".to_string()));
+        assert_eq!(jar.header(&url("http://example.com/docs/x")),
+                   Some("a=1".to_string()));
+        assert_eq!(jar.header(&url("https://example.com/docsx")),
+                   Some("a=1".to_string()));
+    }
+
+    #[test]
+    fn replace_and_expire() {
+        let mut jar = CookieJar::new();
+        let base = url("http://example.com/");
+        jar.set_cookie(&base, "a=1");
+        jar.set_cookie(&base, "a=2");
+        assert_eq!(jar.header(&base), Some("a=2".to_string()));
+        jar.set_cookie(&base, "a=; Max-Age=0");
+        assert_eq!(jar.header(&base), None);
+        jar.set_cookie(&base, "b=1; Expires=Thu, 01 Jan 1970 00:00:01 GMT");
+        assert_eq!(jar.header(&base), None);
+    }
+
+    #[test]
+    fn huge_max_age() {
+        let mut jar = CookieJar::new();
+        let base = url("http://example.com/");
+        jar.set_cookie(&base, "a=1; Max-Age=9223372036854775807");
+        assert_eq!(jar.header(&base), Some("a=1".to_string()));
+    }
+}
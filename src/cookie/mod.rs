@@ -0,0 +1,322 @@
+//! Cookie parsing and `Set-Cookie` building
+//!
+//! This module contains parser of the `Cookie` header (server side),
+//! a builder for the `Set-Cookie` header and a `CookieJar` for the client.
+//!
+use std::fmt;
+use std::str::{Split, from_utf8};
+use std::ascii::AsciiExt;
+use std::time::{Duration, SystemTime};
+
+use tokio_core::io::Io;
+
+use base_serializer::HeaderError;
+use server::{Encoder, HeaderIter};
+use date;
+
+mod jar;
+
+pub use self::jar::CookieJar;
+
+
+/// Iterator over name-value pairs of a single `Cookie` header
+///
+/// Created by `cookie::parse()`. Items without `=` are skipped.
+pub struct Parse<'a> {
+    iter: Split<'a, char>,
+}
+
+/// Iterator over all cookies of a request
+///
+/// Created by `server::Head::cookies()`. Values which aren't valid
+/// utf-8 are skipped.
+pub struct Cookies<'a> {
+    headers: HeaderIter<'a>,
+    current: Option<Parse<'a>>,
+}
+
+/// The value of `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Cookie is never sent with cross-site requests
+    Strict,
+    /// Cookie is sent with top-level cross-site navigations only
+    Lax,
+    /// Cookie is sent with all requests (requires `Secure`)
+    None,
+}
+
+/// A builder of the `Set-Cookie` header
+#[derive(Debug, Clone)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// Parse the value of `Cookie` header into name-value pairs
+///
+/// Surrounding double quotes of the value are stripped, but the value is
+/// not decoded in any other way.
+pub fn parse(value: &str) -> Parse {
+    Parse {
+        iter: value.split(';'),
+    }
+}
+
+/// Iterate over cookies in all `Cookie` headers of the request
+///
+/// Usually it's more convenient to use `server::Head::cookies()`.
+pub fn cookies<'a>(headers: HeaderIter<'a>) -> Cookies<'a> {
+    Cookies {
+        headers: headers,
+        current: None,
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    value.len() > 0 && value.bytes().all(|b| match b {
+        b'!' | b'#' ... b'\'' | b'*' | b'+' | b'-' | b'.' |
+        b'0' ... b'9' | b'A' ... b'Z' | b'^' ... b'z' | b'|' | b'~' => true,
+        _ => false,
+    })
+}
+
+fn is_cookie_value(value: &str) -> bool {
+    value.bytes().all(|b| match b {
+        0x21 | 0x23 ... 0x2B | 0x2D ... 0x3A | 0x3C ... 0x5B |
+        0x5D ... 0x7E => true,
+        _ => false,
+    })
+}
+
+fn is_attribute_value(value: &str) -> bool {
+    value.bytes().all(|b| b >= 0x20 && b < 0x7F && b != b';')
+}
+
+impl<'a> Iterator for Parse<'a> {
+    type Item = (&'a str, &'a str);
+    fn next(&mut self) -> Option<(&'a str, &'a str)> {
+        while let Some(item) = self.iter.next() {
+            let mut pair = item.splitn(2, '=');
+            let name = pair.next().unwrap().trim();
+            let value = match pair.next() {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+            if name.len() == 0 {
+                continue;
+            }
+            let value = if value.len() >= 2 &&
+                value.starts_with('"') && value.ends_with('"')
+            {
+                &value[1..value.len()-1]
+            } else {
+                value
+            };
+            return Some((name, value));
+        }
+        None
+    }
+}
+
+impl<'a> Iterator for Cookies<'a> {
+    type Item = (&'a str, &'a str);
+    fn next(&mut self) -> Option<(&'a str, &'a str)> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                if let Some(pair) = current.next() {
+                    return Some(pair);
+                }
+            }
+            loop {
+                match self.headers.next() {
+                    Some((name, value)) if name.eq_ignore_ascii_case("Cookie")
+                    => {
+                        if let Ok(value) = from_utf8(value) {
+                            self.current = Some(parse(value));
+                            break;
+                        }
+                    }
+                    Some(_) => {}
+                    None => return None,
+                }
+            }
+        }
+    }
+}
+
+impl SetCookie {
+    /// Create a cookie with name and value
+    ///
+    /// By default cookie has no attributes, so it's a session cookie
+    /// for the current host and path.
+    ///
+    /// # Panics
+    ///
+    /// When name is not a valid token, or value contains characters
+    /// not allowed in cookies (whitespace, quotes, commas, semicolons,
+    /// backslashes). Use some encoding (e.g. base64) for arbitrary values.
+    pub fn new(name: &str, value: &str) -> SetCookie {
+        assert!(is_token(name), "invalid cookie name {:?}", name);
+        assert!(is_cookie_value(value), "invalid cookie value {:?}", value);
+        SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+    /// Create a cookie that removes the cookie with the same name
+    ///
+    /// Note: `path` and `domain` must match ones of the original cookie.
+    pub fn remove(name: &str) -> SetCookie {
+        let mut cookie = SetCookie::new(name, "");
+        cookie.max_age(Duration::new(0, 0));
+        return cookie;
+    }
+    /// Set path of the cookie
+    ///
+    /// # Panics
+    ///
+    /// When path contains a semicolon or control characters
+    pub fn path(&mut self, value: &str) -> &mut Self {
+        assert!(is_attribute_value(value), "invalid path {:?}", value);
+        self.path = Some(value.to_string());
+        self
+    }
+    /// Set domain of the cookie
+    ///
+    /// # Panics
+    ///
+    /// When domain contains a semicolon or control characters
+    pub fn domain(&mut self, value: &str) -> &mut Self {
+        assert!(is_attribute_value(value), "invalid domain {:?}", value);
+        self.domain = Some(value.to_string());
+        self
+    }
+    /// Set lifetime of the cookie
+    pub fn max_age(&mut self, value: Duration) -> &mut Self {
+        self.max_age = Some(value);
+        self
+    }
+    /// Set expiration time of the cookie
+    ///
+    /// This is mostly useful for old clients that don't support `Max-Age`.
+    pub fn expires(&mut self, value: SystemTime) -> &mut Self {
+        self.expires = Some(value);
+        self
+    }
+    /// Only send cookie over secure connection
+    pub fn secure(&mut self, value: bool) -> &mut Self {
+        self.secure = value;
+        self
+    }
+    /// Don't allow access to the cookie from javascript
+    pub fn http_only(&mut self, value: bool) -> &mut Self {
+        self.http_only = value;
+        self
+    }
+    /// Set `SameSite` attribute of the cookie
+    pub fn same_site(&mut self, value: SameSite) -> &mut Self {
+        self.same_site = Some(value);
+        self
+    }
+    /// Add the `Set-Cookie` header to the response
+    pub fn add_to<S: Io>(&self, e: &mut Encoder<S>)
+        -> Result<(), HeaderError>
+    {
+        e.add_header("Set-Cookie", self.to_string())
+    }
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None"),
+        }
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{parse, SetCookie, SameSite};
+
+    #[test]
+    fn parse_header() {
+        assert_eq!(parse("a=1; b=\"x y\";c=;bad; =2; d=e=f")
+                   .collect::<Vec<_>>(),
+                   vec![("a", "1"), ("b", "x y"), ("c", ""), ("d", "e=f")]);
+        assert_eq!(parse("").count(), 0);
+    }
+
+    #[test]
+    fn simple_cookie() {
+        assert_eq!(SetCookie::new("sid", "abc").to_string(), "sid=abc");
+        assert_eq!(SetCookie::remove("sid").to_string(), "sid=; Max-Age=0");
+    }
+
+    #[test]
+    fn all_attributes() {
+        let mut cookie = SetCookie::new("sid", "abc");
+        cookie.path("/")
+            .domain("example.com")
+            .max_age(Duration::new(3600, 0))
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(cookie.to_string(),
+            "sid=abc; Path=/; Domain=example.com; Max-Age=3600; \
+             Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; \
+             SameSite=Lax");
+    }
+
+    #[test]
+    #[should_panic(expected="invalid cookie value")]
+    fn invalid_value() {
+        SetCookie::new("sid", "a;b");
+    }
+}
@@ -0,0 +1,166 @@
+//! Formatting and parsing of HTTP dates
+//!
+//! We only support dates from the unix epoch up to the year 9999, which is
+//! good enough for `Expires`, `Last-Modified` and similar headers.
+//!
+use std::ascii::AsciiExt;
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+
+
+const WEEKDAYS: [&'static str; 7] = [
+    "Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&'static str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+
+/// Converts days since epoch into (year, month, day)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Algorithm from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts (year, month, day) into days since epoch
+///
+/// Year must be at least 1970
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153*mp + 2)/5 + day - 1;
+    let doe = yoe * 365 + yoe/4 - yoe/100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Formats time as IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// Times before the epoch are formatted as epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[((days + 4) % 7) as usize],
+        day, MONTHS[(month - 1) as usize], year,
+        (secs % 86400) / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+fn number(token: &str) -> Option<u64> {
+    if token.len() > 0 && token.bytes().all(|b| b >= b'0' && b <= b'9') {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn parse_time(token: &str) -> Option<u64> {
+    let mut parts = token.split(':').map(number);
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(h)), Some(Some(m)), Some(Some(s)), None)
+        if h < 24 && m < 60 && s < 61
+        => Some(h*3600 + m*60 + s),
+        _ => None,
+    }
+}
+
+/// Parses HTTP date
+///
+/// All three formats from RFC 7231 are supported (IMF-fixdate, RFC 850
+/// and asctime), as well as the `Wed, 21-Oct-2015 07:28:00 GMT` which is
+/// common in cookies.
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let mut time = None;
+    let tokens = value.split(|c| c == ' ' || c == ',' || c == '-')
+        .filter(|token| token.len() > 0);
+    for token in tokens {
+        if token.contains(':') {
+            if time.is_some() {
+                return None;
+            }
+            time = parse_time(token);
+            if time.is_none() {
+                return None;
+            }
+        } else if let Some(num) = number(token) {
+            if day.is_none() && token.len() <= 2 {
+                day = Some(num);
+            } else if year.is_none() {
+                year = Some(num);
+            } else {
+                return None;
+            }
+        } else if month.is_none() {
+            month = MONTHS.iter()
+                .position(|m| m.eq_ignore_ascii_case(token))
+                .map(|m| m as u64 + 1);
+        }
+    }
+    let (day, month, year, time) = match (day, month, year, time) {
+        (Some(d), Some(m), Some(y), Some(t)) => (d, m, y, t),
+        _ => return None,
+    };
+    let year = match year {
+        0 ... 69 => year + 2000,
+        70 ... 99 => year + 1900,
+        _ => year,
+    };
+    if year < 1970 || year > 9999 || day < 1 || day > 31 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    days.checked_mul(86400)
+        .and_then(|secs| secs.checked_add(time))
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{UNIX_EPOCH, Duration};
+    use super::{format, parse};
+
+    #[test]
+    fn format_date() {
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(UNIX_EPOCH + Duration::from_secs(784111777)),
+                   "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(UNIX_EPOCH + Duration::from_secs(951782400)),
+                   "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_date() {
+        let time = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), time);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), time);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), time);
+        assert_eq!(parse("Sun, 06-Nov-1994 08:49:37 GMT"), time);
+        assert_eq!(parse("Tue, 29 Feb 2000 00:00:00 GMT"),
+                   Some(UNIX_EPOCH + Duration::from_secs(951782400)));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("Sun, 06 Nov 1994"), None);
+        assert_eq!(parse("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 1960 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 10000 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 300000000000 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 1234567890123 00:00:00 GMT"), None);
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// Enum representing HTTP request method
+///
+/// Methods are case-sensitive, so `get` is parsed as `Other("get")`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    /// Any other (extension) method
+    Other(String),
+}
+
+impl Method {
+    /// Returns the method as it's written in the request line
+    pub fn as_str(&self) -> &str {
+        use self::Method::*;
+        match *self {
+            Get => "GET",
+            Head => "HEAD",
+            Post => "POST",
+            Put => "PUT",
+            Delete => "DELETE",
+            Connect => "CONNECT",
+            Options => "OPTIONS",
+            Trace => "TRACE",
+            Patch => "PATCH",
+            Other(ref name) => name,
+        }
+    }
+    /// Returns true if method is safe (read-only) by RFC 7231
+    ///
+    /// Extension methods are never considered safe.
+    pub fn is_safe(&self) -> bool {
+        use self::Method::*;
+        match *self {
+            Get | Head | Options | Trace => true,
+            _ => false,
+        }
+    }
+    /// Returns true if method is idempotent by RFC 7231
+    ///
+    /// I.e. request with this method may be retried safely if connection
+    /// was closed before receiving a response.
+    pub fn is_idempotent(&self) -> bool {
+        use self::Method::*;
+        match *self {
+            Get | Head | Options | Trace | Put | Delete => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Method {
+    fn from(name: &'a str) -> Method {
+        use self::Method::*;
+        match name {
+            "GET" => Get,
+            "HEAD" => Head,
+            "POST" => Post,
+            "PUT" => Put,
+            "DELETE" => Delete,
+            "CONNECT" => Connect,
+            "OPTIONS" => Options,
+            "TRACE" => Trace,
+            "PATCH" => Patch,
+            _ => Other(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Method;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Method::from("GET"), Method::Get);
+        assert_eq!(Method::from("PATCH"), Method::Patch);
+        assert_eq!(Method::from("get"), Method::Other("get".into()));
+        assert_eq!(Method::from("PROPFIND").as_str(), "PROPFIND");
+    }
+
+    #[test]
+    fn idempotent() {
+        assert!(Method::Put.is_idempotent());
+        assert!(!Method::Put.is_safe());
+        assert!(!Method::Post.is_idempotent());
+        assert!(!Method::Other("LOCK".into()).is_idempotent());
+    }
+}
@@ -1,5 +1,7 @@
 mod status;
 mod version;
+mod method;
 
 pub use self::status::*;
 pub use self::version::*;
+pub use self::method::*;
@@ -46,6 +46,7 @@ pub enum Status {
     ExpectationFailed,              // 417
     UpgradeRequired,                // 426
     TooManyRequests,                // 429
+    RequestHeaderFieldsTooLarge,    // 431
     //  5xx status codes
     InternalServerError,            // 500
     NotImplemented,                 // 501
@@ -102,6 +103,7 @@ impl Status {
             Status::ExpectationFailed               => 417,
             Status::UpgradeRequired                 => 426,
             Status::TooManyRequests                 => 429,
+            Status::RequestHeaderFieldsTooLarge     => 431,
             //  5xx status codes
             Status::InternalServerError             => 500,
             Status::NotImplemented                  => 501,
@@ -156,6 +158,7 @@ impl Status {
             417 => "Expectation Failed",
             426 => "Upgrade Required",
             429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
             //  5xx codes
             500 => "Internal Server Error",
             501 => "Not Implemented",
@@ -221,6 +224,7 @@ impl Status {
             417 => ExpectationFailed,
             426 => UpgradeRequired,
             429 => TooManyRequests,
+            431 => RequestHeaderFieldsTooLarge,
             //  5xx
             500 => InternalServerError,
             501 => NotImplemented,
@@ -11,6 +11,8 @@ extern crate tokio_core;
 extern crate netbuf;
 extern crate tk_bufstream;
 extern crate byteorder;
+extern crate flate2;
+#[cfg(feature="sendfile")] extern crate tk_sendfile;
 #[macro_use(quick_error)] extern crate quick_error;
 #[macro_use] extern crate matches;
 #[macro_use] extern crate log;
@@ -19,10 +21,13 @@ extern crate byteorder;
 pub mod server;
 pub mod client;
 pub mod websocket;
+pub mod cookie;
 mod enums;
 mod headers;
 mod base_serializer;
 mod chunked;
 mod body_parser;
+mod date;
+mod base64;
 
-pub use enums::{Version, Status};
+pub use enums::{Version, Status, Method};
@@ -3,17 +3,20 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::marker::PhantomData;
+use std::borrow::Cow;
 
 use futures::{Async, Future, IntoFuture};
 use futures::future::FutureResult;
 use tokio_core::io::Io;
 use tokio_core::reactor::Handle;
 use tk_bufstream::{ReadBuf, WriteBuf, ReadFramed, WriteFramed};
+use url::form_urlencoded;
 
 use websocket::{ServerCodec as WebsocketCodec};
 use super::{Error, Encoder, EncoderDone, Dispatcher, Codec, Head, RecvMode};
 use super::{WebsocketHandshake};
-use {Version};
+use super::path;
+use {Version, Method};
 
 /// Buffered request struct
 ///
@@ -22,7 +25,7 @@ use {Version};
 #[derive(Debug)]
 pub struct Request {
     peer_addr: SocketAddr,
-    method: String,
+    method: Method,
     path: String,
     host: Option<String>,
     version: Version,
@@ -140,13 +143,37 @@ impl Request {
         self.peer_addr
     }
     /// Returns method of a request
-    pub fn method(&self) -> &str {
+    pub fn method(&self) -> &Method {
         &self.method
     }
     /// Returns path of a request
     pub fn path(&self) -> &str {
         &self.path
     }
+    /// Returns path of a request without query string, not decoded
+    pub fn raw_path(&self) -> &str {
+        path::split_query(&self.path).0
+    }
+    /// Returns query string of a request (the part after `?`)
+    pub fn query(&self) -> Option<&str> {
+        path::split_query(&self.path).1
+    }
+    /// Returns percent-decoded path without query string
+    ///
+    /// See `Head::decoded_path()` for details.
+    pub fn decoded_path(&self) -> Option<Cow<str>> {
+        path::decode(self.raw_path())
+    }
+    /// Returns percent-decoded path if it can't escape the root directory
+    ///
+    /// See `Head::safe_path()` for details.
+    pub fn safe_path(&self) -> Option<Cow<str>> {
+        path::decode_safe(self.raw_path())
+    }
+    /// Iterates over decoded key-value pairs of the query string
+    pub fn query_pairs(&self) -> form_urlencoded::Parse {
+        path::query_pairs(self.query())
+    }
     /// Returns HTTP version used in request
     pub fn version(&self) -> Version {
         self.version
@@ -254,7 +281,7 @@ impl<S: Io, N: NewService<S>> Dispatcher<S> for BufferedDispatcher<S, N> {
             service: self.service.new(),
             request: Some(Request {
                 peer_addr: self.addr,
-                method: headers.method().to_string(),
+                method: headers.method().clone(),
                 // TODO(tailhook) process other forms of path
                 path: headers.path().unwrap().to_string(),
                 host: headers.host().map(|x| x.to_string()),
@@ -290,8 +317,8 @@ impl<S: Io, R: Service<S>> Codec<S> for BufferedCodec<R> {
         self.service.call(self.request.take().unwrap(), e)
     }
     fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>){
-        let inp = read_buf.framed(WebsocketCodec);
-        let out = write_buf.framed(WebsocketCodec);
+        let inp = read_buf.framed(WebsocketCodec::new());
+        let out = write_buf.framed(WebsocketCodec::new());
         self.handle.spawn(self.service.start_websocket(out, inp));
     }
 }
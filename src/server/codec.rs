@@ -4,6 +4,7 @@ use tk_bufstream::{ReadBuf, WriteBuf};
 
 use super::{Error, Encoder, EncoderDone, Head};
 use super::RecvMode;
+use enums::Status;
 
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -29,6 +30,28 @@ pub trait Dispatcher<S: Io> {
     /// (for example on `self`) for further processing.
     fn headers_received(&mut self, headers: &Head)
         -> Result<Self::Codec, Error>;
+
+    /// Write a response for a request that can't be processed
+    ///
+    /// This is called when request is invalid (i.e. headers can't be
+    /// parsed or request body is too large). Connection is closed after
+    /// the response, so `Connection: close` is added automatically.
+    ///
+    /// Default implementation writes a short plain-text body. Override
+    /// it to customize error pages.
+    fn bad_request(&mut self, status: Status, _error: &Error,
+        mut e: Encoder<S>)
+        -> EncoderDone<S>
+    {
+        let body = format!("{} {}\n", status.code(), status.reason());
+        e.status(status);
+        e.add_header("Content-Type", "text/plain").unwrap();
+        e.add_length(body.len() as u64).unwrap();
+        if e.done_headers().unwrap() {
+            e.write_body(body.as_bytes());
+        }
+        e.done()
+    }
 }
 
 /// The type represents a consumer of a single request and yields a writer of
@@ -70,6 +93,17 @@ pub trait Codec<S: Io> {
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, Error>;
 
+    /// Trailers of the request received
+    ///
+    /// Called once, after the last `data_received` has consumed the whole
+    /// body. Trailers may only be sent with a chunked body, for other
+    /// kinds of body the list is empty.
+    fn trailers_received(&mut self, _trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        Ok(())
+    }
+
     /// Start writing a response
     ///
     /// This method is called when there all preceding requests are either
@@ -104,6 +138,11 @@ impl<S: Io, F> Codec<S> for Box<Codec<S, ResponseFuture=F>>
     {
         (**self).data_received(data, end)
     }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        (**self).trailers_received(trailers)
+    }
     fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
         (**self).start_response(e)
     }
@@ -0,0 +1,563 @@
+//! Compression of response bodies
+//!
+//! Compression is opt-in: negotiate encoding in `Dispatcher` (while you
+//! have access to `Head`) and wrap the `Encoder` into `CompressEncoder`
+//! in `Codec::start_response`:
+//!
+//! ```rust,ignore
+//! // in headers_received()
+//! let encoding = compress::negotiate(headers);
+//! // in start_response()
+//! let mut e = CompressEncoder::new(e, encoding);
+//! e.status(Status::Ok);
+//! e.add_length(body.len() as u64).unwrap();
+//! if e.done_headers().unwrap() {
+//!     e.write_body(body);
+//! }
+//! Box::new(ok(e.done()))
+//! ```
+//!
+//! When compressing, `Content-Length` is replaced by chunked encoding.
+//! Responses with status 204, 206 and 304 and responses that already have
+//! `Content-Encoding` are sent as is.
+//!
+use std::io::{self, Write};
+use std::ascii::AsciiExt;
+use std::fmt::Display;
+use std::mem::replace;
+use std::str::from_utf8;
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::{Async, Future, Poll};
+use tokio_core::io::Io;
+
+use base_serializer::HeaderError;
+use enums::{Method, Status};
+use super::{Encoder, EncoderDone, Head};
+use super::encoder;
+
+
+/// Content encoding of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// `gzip`, preferred if client doesn't have a preference
+    Gzip,
+    /// `deflate`, which is actually a zlib stream
+    Deflate,
+}
+
+/// A wrapper around `Encoder` that compresses the response body
+///
+/// Has the same interface as `Encoder`. Note that if response is
+/// compressed, `add_length` does not limit the size of the body.
+pub struct CompressEncoder<S: Io> {
+    inner: Encoder<S>,
+    state: State,
+}
+
+/// A future that yields `CompressEncoder` again after buffer is flushed
+///
+/// This future is created by `CompressEncoder::wait_flush(x)`
+pub struct WaitFlush<S: Io> {
+    inner: encoder::WaitFlush<S>,
+    state: Option<State>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Length {
+    Fixed(u64),
+    Chunked,
+}
+
+struct State {
+    encoding: Option<Encoding>,
+    /// Headers are written directly (status doesn't allow compression)
+    passthrough: bool,
+    /// Response has `Content-Encoding` already
+    encoded: bool,
+    length: Option<Length>,
+    compressor: Option<Compressor>,
+}
+
+enum Compressor {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+/// Chooses the best encoding from `Accept-Encoding` header
+///
+/// Returns `None` for `HEAD` requests and when no supported encoding is
+/// acceptable. Quality values are respected, `gzip` wins on a tie.
+pub fn negotiate(head: &Head) -> Option<Encoding> {
+    if *head.method() == Method::Head {
+        return None;
+    }
+    choose(head.headers()
+        .filter(|&(name, _)| name.eq_ignore_ascii_case("Accept-Encoding"))
+        .filter_map(|(_, value)| from_utf8(value).ok()))
+}
+
+fn choose<'a, I: Iterator<Item=&'a str>>(values: I) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+    for value in values {
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap().trim();
+            let mut quality = 1.0;
+            for param in parts {
+                let param = param.trim();
+                if param.starts_with("q=") || param.starts_with("Q=") {
+                    quality = param[2..].trim().parse().unwrap_or(0.0);
+                }
+            }
+            if coding.eq_ignore_ascii_case("gzip") ||
+               coding.eq_ignore_ascii_case("x-gzip")
+            {
+                gzip = Some(quality);
+            } else if coding.eq_ignore_ascii_case("deflate") {
+                deflate = Some(quality);
+            } else if coding == "*" {
+                any = Some(quality);
+            }
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+impl Encoding {
+    /// Returns the value for the `Content-Encoding` header
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+impl Compressor {
+    fn new(encoding: Encoding) -> Compressor {
+        match encoding {
+            Encoding::Gzip => Compressor::Gzip(
+                GzEncoder::new(Vec::new(), Compression::Default)),
+            Encoding::Deflate => Compressor::Deflate(
+                ZlibEncoder::new(Vec::new(), Compression::Default)),
+        }
+    }
+    /// Returns compressed data produced so far
+    ///
+    /// Writes to a vector never fail, so errors are not expected here.
+    fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Compressor::Gzip(ref mut e) => {
+                e.write_all(data).expect("compress to vector");
+                replace(e.get_mut(), Vec::new())
+            }
+            Compressor::Deflate(ref mut e) => {
+                e.write_all(data).expect("compress to vector");
+                replace(e.get_mut(), Vec::new())
+            }
+        }
+    }
+    /// Returns the rest of the stream, including gzip trailer
+    fn finish(&mut self) -> Vec<u8> {
+        match *self {
+            Compressor::Gzip(ref mut e) => {
+                e.try_finish().expect("compress to vector");
+                replace(e.get_mut(), Vec::new())
+            }
+            Compressor::Deflate(ref mut e) => {
+                e.try_finish().expect("compress to vector");
+                replace(e.get_mut(), Vec::new())
+            }
+        }
+    }
+}
+
+impl State {
+    /// Finishes compressed stream (if any) and writes the rest of it
+    fn finish<S: Io>(&mut self, e: &mut Encoder<S>) {
+        if let Some(mut compressor) = self.compressor.take() {
+            e.write_body(&compressor.finish());
+        }
+    }
+}
+
+impl<S: Io> CompressEncoder<S> {
+    /// Wrap the encoder, `encoding` is usually a result of `negotiate()`
+    ///
+    /// If `encoding` is `None` the response is not compressed, but
+    /// `Vary: Accept-Encoding` is still added when appropriate.
+    pub fn new(e: Encoder<S>, encoding: Option<Encoding>)
+        -> CompressEncoder<S>
+    {
+        CompressEncoder {
+            inner: e,
+            state: State {
+                encoding: encoding,
+                passthrough: false,
+                encoded: false,
+                length: None,
+                compressor: None,
+            },
+        }
+    }
+    fn check_status(&mut self, code: u16) {
+        // Partial content can't be compressed because `Content-Range`
+        // refers to the bytes of uncompressed representation
+        self.state.passthrough = code < 200 ||
+            code == 204 || code == 206 || code == 304;
+    }
+    /// Write status line using `Status` enum, see `Encoder::status`
+    pub fn status(&mut self, status: Status) {
+        self.check_status(status.code());
+        self.inner.status(status)
+    }
+    /// Write custom status line, see `Encoder::custom_status`
+    pub fn custom_status(&mut self, code: u16, reason: &str) {
+        self.check_status(code);
+        self.inner.custom_status(code, reason)
+    }
+    /// Add a header to the message, see `Encoder::add_header`
+    ///
+    /// If `Content-Encoding` is added, the response is not compressed.
+    pub fn add_header<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        self.inner.add_header(name, value)?;
+        if name.eq_ignore_ascii_case("Content-Encoding") {
+            self.state.encoded = true;
+        }
+        Ok(())
+    }
+    /// Same as `add_header` but allows value to be formatted directly
+    pub fn format_header<D: Display>(&mut self, name: &str, value: D)
+        -> Result<(), HeaderError>
+    {
+        self.inner.format_header(name, value)?;
+        if name.eq_ignore_ascii_case("Content-Encoding") {
+            self.state.encoded = true;
+        }
+        Ok(())
+    }
+    /// Add a content length to the message
+    ///
+    /// The header is only written if the response is not compressed,
+    /// otherwise chunked encoding is used.
+    pub fn add_length(&mut self, n: u64) -> Result<(), HeaderError> {
+        if self.state.passthrough {
+            return self.inner.add_length(n);
+        }
+        match self.state.length {
+            Some(Length::Fixed(_)) => {
+                Err(HeaderError::DuplicateContentLength)
+            }
+            Some(Length::Chunked) => {
+                Err(HeaderError::ContentLengthAfterTransferEncoding)
+            }
+            None => {
+                self.state.length = Some(Length::Fixed(n));
+                Ok(())
+            }
+        }
+    }
+    /// Sets the transfer encoding to chunked
+    pub fn add_chunked(&mut self) -> Result<(), HeaderError> {
+        if self.state.passthrough {
+            return self.inner.add_chunked();
+        }
+        match self.state.length {
+            Some(Length::Fixed(_)) => {
+                Err(HeaderError::TransferEncodingAfterContentLength)
+            }
+            Some(Length::Chunked) => {
+                Err(HeaderError::DuplicateTransferEncoding)
+            }
+            None => {
+                self.state.length = Some(Length::Chunked);
+                Ok(())
+            }
+        }
+    }
+    /// Returns true if at least `status()` method has been called
+    pub fn is_started(&self) -> bool {
+        self.inner.is_started()
+    }
+    /// Returns the encoding if the response body is compressed
+    ///
+    /// Only meaningful after `done_headers()`.
+    pub fn encoding(&self) -> Option<Encoding> {
+        if self.state.compressor.is_some() {
+            self.state.encoding
+        } else {
+            None
+        }
+    }
+    /// Closes the HTTP header and returns `true` if body is expected
+    ///
+    /// This is where we decide whether to compress the response.
+    pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
+        if !self.state.passthrough && !self.state.encoded {
+            self.inner.add_header("Vary", "Accept-Encoding")?;
+            if let Some(encoding) = self.state.encoding {
+                if self.state.length.is_none() {
+                    return Err(HeaderError::CantDetermineBodySize);
+                }
+                self.inner.add_header("Content-Encoding",
+                                      encoding.as_str())?;
+                self.state.length = Some(Length::Chunked);
+                self.state.compressor = Some(Compressor::new(encoding));
+            }
+        }
+        match self.state.length.take() {
+            Some(Length::Fixed(n)) => self.inner.add_length(n)?,
+            Some(Length::Chunked) => self.inner.add_chunked()?,
+            None => {}
+        }
+        self.inner.done_headers()
+    }
+    /// Write (and compress) a chunk of the message body
+    pub fn write_body(&mut self, data: &[u8]) {
+        match self.state.compressor {
+            Some(ref mut compressor) => {
+                self.inner.write_body(&compressor.compress(data));
+            }
+            None => self.inner.write_body(data),
+        }
+    }
+    /// Add a trailer to the chunked message
+    ///
+    /// Compressed stream is finished before, so no more body can be
+    /// written after this call.
+    pub fn add_trailer<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        self.state.finish(&mut self.inner);
+        self.inner.add_trailer(name, value)
+    }
+    /// Returns number of bytes buffered (compressed) so far
+    pub fn bytes_buffered(&self) -> usize {
+        self.inner.bytes_buffered()
+    }
+    /// Returns a future which yields the encoder back after buffer is
+    /// flushed, see `Encoder::wait_flush`
+    pub fn wait_flush(self, watermark: usize) -> WaitFlush<S> {
+        WaitFlush {
+            inner: self.inner.wait_flush(watermark),
+            state: Some(self.state),
+        }
+    }
+    /// Finish the compressed stream and the response
+    pub fn done(mut self) -> EncoderDone<S> {
+        self.state.finish(&mut self.inner);
+        self.inner.done()
+    }
+}
+
+impl<S: Io> io::Write for CompressEncoder<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_body(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Io> Future for WaitFlush<S> {
+    type Item = CompressEncoder<S>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<CompressEncoder<S>, io::Error> {
+        match self.inner.poll()? {
+            Async::Ready(e) => Ok(Async::Ready(CompressEncoder {
+                inner: e,
+                state: self.state.take().expect("future is polled twice"),
+            })),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::str::from_utf8;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use tk_bufstream::{IoBuf, MockData};
+
+    use enums::{Status, Version};
+    use server::encoder::{self, ResponseConfig, get_inner};
+    use super::{CompressEncoder, Compressor, Encoding, choose};
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        GzDecoder::new(data).unwrap().read_to_end(&mut result).unwrap();
+        return result;
+    }
+
+    fn negotiate(values: &[&str]) -> Option<Encoding> {
+        choose(values.iter().map(|x| *x))
+    }
+
+    fn response<F>(encoding: Option<Encoding>, f: F) -> (String, Vec<u8>)
+        where F: FnOnce(&mut CompressEncoder<MockData>)
+    {
+        let mock = MockData::new();
+        let (output, _) = IoBuf::new(mock.clone()).split();
+        let mut e = CompressEncoder::new(encoder::new(output,
+            ResponseConfig {
+                is_head: false,
+                do_close: false,
+                version: Version::Http11,
+                expect_continue: false,
+                continue_sent: false,
+            }, &Default::default()), encoding);
+        f(&mut e);
+        get_inner(e.done()).flush().unwrap();
+        let data = mock.output(..);
+        let end = data.windows(4).position(|x| x == b"\r\n\r\n")
+            .expect("headers are written") + 4;
+        (String::from_utf8(data[..end].to_vec()).unwrap(),
+         data[end..].to_vec())
+    }
+
+    fn dechunk(mut data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        loop {
+            let line = data.iter().position(|&x| x == b'\r').unwrap();
+            let size = usize::from_str_radix(
+                from_utf8(&data[..line]).unwrap(), 16).unwrap();
+            data = &data[line+2..];
+            if size == 0 {
+                assert_eq!(data, b"\r\n");
+                return result;
+            }
+            result.extend_from_slice(&data[..size]);
+            assert_eq!(&data[size..size+2], b"\r\n");
+            data = &data[size+2..];
+        }
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!(negotiate(&[]), None);
+        assert_eq!(negotiate(&["gzip, deflate, br"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["deflate", "x-gzip;q=0.5"]),
+                   Some(Encoding::Deflate));
+        assert_eq!(negotiate(&["gzip;q=0, deflate;q=0"]), None);
+        assert_eq!(negotiate(&["identity, *;q=0.1"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["gzip;q=0, *"]), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&["br"]), None);
+    }
+
+    #[test]
+    fn gzip() {
+        let mut c = Compressor::new(Encoding::Gzip);
+        let mut data = c.compress(b"hello ");
+        data.extend(c.compress(b"world"));
+        data.extend(c.finish());
+        assert_eq!(&data[..3], &[0x1f, 0x8b, 8]);
+        assert_eq!(gunzip(&data), b"hello world");
+    }
+
+    #[test]
+    fn deflate() {
+        let mut c = Compressor::new(Encoding::Deflate);
+        let mut data = c.compress(b"hello world");
+        data.extend(c.finish());
+        let mut result = Vec::new();
+        ZlibDecoder::new(&data[..]).read_to_end(&mut result).unwrap();
+        assert_eq!(result, b"hello world");
+    }
+
+    #[test]
+    fn empty_gzip() {
+        let mut c = Compressor::new(Encoding::Gzip);
+        assert_eq!(gunzip(&c.finish()), b"");
+    }
+
+    #[test]
+    fn compressed() {
+        let (head, body) = response(Some(Encoding::Gzip), |e| {
+            e.status(Status::Ok);
+            e.add_length(11).unwrap();
+            assert!(e.done_headers().unwrap());
+            e.write_body(b"hello world");
+        });
+        assert_eq!(head, "HTTP/1.1 200 OK\r\n\
+                          Vary: Accept-Encoding\r\n\
+                          Content-Encoding: gzip\r\n\
+                          Transfer-Encoding: chunked\r\n\r\n");
+        assert_eq!(gunzip(&dechunk(&body)), b"hello world");
+    }
+
+    #[test]
+    fn not_acceptable() {
+        let (head, body) = response(None, |e| {
+            e.status(Status::Ok);
+            e.add_length(11).unwrap();
+            assert!(e.done_headers().unwrap());
+            e.write_body(b"hello world");
+        });
+        assert_eq!(head, "HTTP/1.1 200 OK\r\n\
+                          Vary: Accept-Encoding\r\n\
+                          Content-Length: 11\r\n\r\n");
+        assert_eq!(body, b"hello world");
+    }
+
+    #[test]
+    fn already_encoded() {
+        let (head, body) = response(Some(Encoding::Gzip), |e| {
+            e.status(Status::Ok);
+            e.add_header("Content-Encoding", "br").unwrap();
+            e.add_length(3).unwrap();
+            assert!(e.done_headers().unwrap());
+            e.write_body(b"xyz");
+        });
+        assert_eq!(head, "HTTP/1.1 200 OK\r\n\
+                          Content-Encoding: br\r\n\
+                          Content-Length: 3\r\n\r\n");
+        assert_eq!(body, b"xyz");
+    }
+
+    #[test]
+    fn partial_content() {
+        let (head, body) = response(Some(Encoding::Gzip), |e| {
+            e.status(Status::PartialContent);
+            e.add_header("Content-Range", "bytes 0-4/11").unwrap();
+            e.add_length(5).unwrap();
+            assert!(e.done_headers().unwrap());
+            e.write_body(b"hello");
+        });
+        assert_eq!(head, "HTTP/1.1 206 Partial Content\r\n\
+                          Content-Range: bytes 0-4/11\r\n\
+                          Content-Length: 5\r\n\r\n");
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn bodyless() {
+        for &(status, line) in &[
+            (Status::NoContent, "204 No Content"),
+            (Status::NotModified, "304 Not Modified"),
+        ] {
+            let (head, body) = response(Some(Encoding::Gzip), |e| {
+                e.status(status);
+                assert!(!e.done_headers().unwrap());
+            });
+            assert_eq!(head, format!("HTTP/1.1 {}\r\n\r\n", line));
+            assert_eq!(body, b"");
+        }
+    }
+}
@@ -16,6 +16,8 @@ impl Config {
             input_body_whole_timeout: Duration::new(3600, 0),
             output_body_byte_timeout: Duration::new(15, 0),
             output_body_whole_timeout: Duration::new(3600, 0),
+            max_connections: 10000,
+            accept_error_delay: Duration::from_millis(100),
         }
     }
     /// A number of inflight requests until we stop reading more requests
@@ -83,4 +85,20 @@ impl Config {
         self.output_body_whole_timeout = value;
         self
     }
+    /// Maximum number of connections served at the same time by `serve()`
+    ///
+    /// When limit is reached, no more connections are accepted until some
+    /// of the current ones are closed.
+    pub fn max_connections(&mut self, value: usize) -> &mut Self {
+        self.max_connections = value;
+        self
+    }
+    /// Delay accepting connections for this time after accept error
+    ///
+    /// Accept errors are usually caused by running out of file descriptors
+    /// (EMFILE), so retrying immediately just burns CPU.
+    pub fn accept_error_delay(&mut self, value: Duration) -> &mut Self {
+        self.accept_error_delay = value;
+        self
+    }
 }
@@ -1,12 +1,14 @@
 use std::io;
 use std::fmt::Display;
+use std::rc::Rc;
+use std::cell::Cell;
 
-use futures::{Future, Poll};
+use futures::{Future, Poll, Async};
 use tokio_core::io::Io;
 use tk_bufstream::{WriteBuf, WriteRaw, FutureWriteRaw};
 
 use base_serializer::{MessageState, HeaderError};
-use enums::{Version, Status};
+use enums::{Version, Status, Method};
 use super::headers::Head;
 
 
@@ -17,8 +19,26 @@ use super::headers::Head;
 pub struct Encoder<S: Io> {
     state: MessageState,
     io: WriteBuf<S>,
+    progress: Rc<FlushProgress>,
 }
 
+/// Progress of writing the response, shared with the protocol
+///
+/// This is used to apply `output_body_byte_timeout` when response waits
+/// for the client in `Encoder::wait_flush`
+#[derive(Debug, Default)]
+pub struct FlushProgress {
+    /// Response is waiting for the buffer to be flushed
+    pub waiting: Cell<bool>,
+    /// Number of bytes flushed by `WaitFlush` futures
+    pub bytes: Cell<usize>,
+}
+
+/// A future that yields `Encoder` again after buffer has less bytes
+///
+/// This future is created by `Encoder::wait_flush(x)`
+pub struct WaitFlush<S: Io>(Option<Encoder<S>>, usize);
+
 /// This structure returned from `Encoder::done` and works as a continuation
 /// that should be returned from the future that writes request.
 pub struct EncoderDone<S: Io> {
@@ -37,6 +57,10 @@ pub struct ResponseConfig {
     pub do_close: bool,
     /// Version of HTTP request
     pub version: Version,
+    /// Client waits for `100 Continue` before sending request body
+    pub expect_continue: bool,
+    /// The `100 Continue` is already written by the protocol
+    pub continue_sent: bool,
 }
 
 /// A future that yields `RawBody` after buffer is empty
@@ -69,6 +93,10 @@ impl<S: Io> Encoder<S> {
     ///
     /// When the response is already started. It's expected that your response
     /// handler state machine will never call the method twice.
+    ///
+    /// Note: the protocol sends `100 Continue` by itself when request
+    /// contains an expectation and codec starts reading the request body.
+    /// In this case the method does nothing.
     pub fn response_continue(&mut self) {
         self.state.response_continue(&mut self.io.out_buf)
     }
@@ -211,6 +239,21 @@ impl<S: Io> Encoder<S> {
     pub fn write_body(&mut self, data: &[u8]) {
         self.state.write_body(&mut self.io.out_buf, data)
     }
+    /// Add a trailer to the chunked message
+    ///
+    /// The last chunk is written before the first trailer, so no more
+    /// body can be written after this call. Same validation rules as in
+    /// `add_header` apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_trailer` is called before `done_headers` or
+    /// when the body is not chunked.
+    pub fn add_trailer<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        self.state.add_trailer(&mut self.io.out_buf, name, value.as_ref())
+    }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
     pub fn is_complete(&self) -> bool {
@@ -228,6 +271,21 @@ impl<S: Io> Encoder<S> {
         self.state.done(&mut self.io.out_buf);
         EncoderDone { buf: self.io }
     }
+    /// Returns number of bytes in the output buffer, not yet sent
+    pub fn bytes_buffered(&self) -> usize {
+        self.io.out_buf.len()
+    }
+    /// Returns a future which yields encoder back when buffer is flushed
+    ///
+    /// More specifically when `bytes_buffered()` is less than or equal
+    /// to the `watermark`. This allows to stream large bodies with bounded
+    /// memory usage.
+    ///
+    /// If client doesn't read data for `output_body_byte_timeout`, while
+    /// we are waiting for the flush, connection is closed.
+    pub fn wait_flush(self, watermark: usize) -> WaitFlush<S> {
+        WaitFlush(Some(self), watermark)
+    }
     /// Returns a raw body for zero-copy writing techniques
     ///
     /// Note: we don't assert on the format of the body if you're using this
@@ -286,18 +344,32 @@ pub fn get_inner<S: Io>(e: EncoderDone<S>) -> WriteBuf<S> {
     e.buf
 }
 
-pub fn new<S: Io>(io: WriteBuf<S>, cfg: ResponseConfig) -> Encoder<S> {
+pub fn new<S: Io>(io: WriteBuf<S>, cfg: ResponseConfig,
+    progress: &Rc<FlushProgress>)
+    -> Encoder<S>
+{
     use base_serializer::Body::*;
 
     // TODO(tailhook) implement Connection: Close,
     // (including explicit one in HTTP/1.0) and maybe others
+    let body = if cfg.is_head { Head } else { Normal };
+    let close = cfg.do_close || cfg.version == Version::Http10;
     Encoder {
-        state: MessageState::ResponseStart {
-            body: if cfg.is_head { Head } else { Normal },
-            version: cfg.version,
-            close: cfg.do_close || cfg.version == Version::Http10,
+        state: if cfg.continue_sent {
+            MessageState::FinalResponseStart {
+                body: body,
+                version: cfg.version,
+                close: close,
+            }
+        } else {
+            MessageState::ResponseStart {
+                body: body,
+                version: cfg.version,
+                close: close,
+            }
         },
         io: io,
+        progress: progress.clone(),
     }
 }
 
@@ -305,8 +377,45 @@ impl ResponseConfig {
     pub fn from(req: &Head) -> ResponseConfig {
         ResponseConfig {
             version: req.version(),
-            is_head: req.method() == "HEAD",
+            is_head: *req.method() == Method::Head,
             do_close: req.connection_close(),
+            expect_continue: req.expect_continue()
+                && req.version() == Version::Http11
+                && req.has_body(),
+            continue_sent: false,
+        }
+    }
+}
+
+impl<S: Io> Future for WaitFlush<S> {
+    type Item = Encoder<S>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Encoder<S>, io::Error> {
+        let bytes_left = {
+            let enc = self.0.as_mut().expect("future is polled twice");
+            let old_len = enc.io.out_buf.len();
+            enc.io.flush()?;
+            let new_len = enc.io.out_buf.len();
+            if new_len < old_len {
+                let bytes = enc.progress.bytes.get();
+                enc.progress.bytes.set(bytes.wrapping_add(old_len - new_len));
+            }
+            enc.progress.waiting.set(new_len > self.1);
+            new_len
+        };
+        if bytes_left <= self.1 {
+            Ok(Async::Ready(self.0.take().unwrap()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl<S: Io> Drop for WaitFlush<S> {
+    fn drop(&mut self) {
+        // Response future may drop us without waiting for the flush
+        if let Some(ref enc) = self.0 {
+            enc.progress.waiting.set(false);
         }
     }
 }
@@ -3,6 +3,10 @@ use std::fmt;
 use std::convert::From;
 
 use httparse;
+use log::LogLevel;
+
+use enums::Status;
+use chunked;
 
 /// HTTP server error
 pub struct Error(ErrorEnum);
@@ -28,6 +32,11 @@ quick_error! {
             description("chunk size parse error")
             from()
         }
+        /// Error parsing trailers of chunked request body
+        TrailersParseError(err: httparse::Error) {
+            description("error parsing trailers")
+            display("error parsing trailers: {:?}", err)
+        }
         /// Connection reset
         ConnectionReset {
             description("connection reset")
@@ -77,6 +86,15 @@ quick_error! {
     }
 }
 
+impl From<chunked::Error> for ErrorEnum {
+    fn from(err: chunked::Error) -> ErrorEnum {
+        match err {
+            chunked::Error::ChunkSize(e) => ErrorEnum::ChunkParseError(e),
+            chunked::Error::Trailers(e) => ErrorEnum::TrailersParseError(e),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(&self.0, f)
@@ -113,6 +131,46 @@ impl Error {
     }
 }
 
+/// Returns status of the response that should be sent for the error
+///
+/// Returns `None` if it's not a fault of a client (or if connection is
+/// broken anyway), so no response is needed.
+pub fn response_status(err: &Error) -> Option<Status> {
+    use self::ErrorEnum::*;
+    match err.0 {
+        ParseError(httparse::Error::TooManyHeaders)
+        => Some(Status::RequestHeaderFieldsTooLarge),
+        ParseError(..) | ChunkParseError(..) | TrailersParseError(..)
+        | BadRequestTarget
+        | HostInvalid | DuplicateHost | ConnectionInvalid
+        | ContentLengthInvalid | DuplicateContentLength
+        => Some(Status::BadRequest),
+        RequestTooLong => Some(Status::RequestEntityTooLarge),
+        UnsupportedBody => Some(Status::NotImplemented),
+        Io(..) | ConnectionReset | Timeout | Custom(..) => None,
+    }
+}
+
+/// Returns the level which connection error should be logged with
+///
+/// Errors caused by clients (bad requests, timeouts, resets) are too
+/// common to be logged with high level.
+pub fn log_level(err: &Error) -> LogLevel {
+    use self::ErrorEnum::*;
+    match err.0 {
+        Io(ref e) => match e.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            => LogLevel::Debug,
+            _ => LogLevel::Info,
+        },
+        ConnectionReset | Timeout => LogLevel::Debug,
+        Custom(..) => LogLevel::Error,
+        _ => LogLevel::Debug,
+    }
+}
+
 #[test]
 fn send_sync() {
     fn send_sync<T: Send+Sync>(_: T) {}
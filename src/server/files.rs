@@ -0,0 +1,845 @@
+//! Serving static files from a directory
+//!
+//! `Files` maps request path to a file in the root directory, and writes
+//! the response with `Content-Type`, `Last-Modified` and `ETag` headers.
+//! Conditional requests (`If-None-Match`, `If-Modified-Since`) are answered
+//! with `304 Not Modified` and `Range` requests with `206 Partial Content`
+//! (including `multipart/byteranges` for multiple ranges) or with
+//! `416 Range Not Satisfiable`.
+//!
+//! Paths containing `..` or encoded slashes are never resolved and
+//! symlinks pointing outside of the root directory aren't followed, so files
+//! outside of the root directory can't be served.
+//!
+//! Note: files are read in the event loop thread in chunks (only as fast
+//! as the client reads them). With the `sendfile` feature, `serve_sendfile`
+//! sends whole files with zero-copy using a `DiskPool` from `tk-sendfile`.
+//!
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fs::{File, Metadata};
+use std::mem::replace;
+use std::path::{Path, PathBuf, Component};
+use std::time::UNIX_EPOCH;
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+use std::collections::VecDeque;
+
+use futures::{Async, Future, Poll};
+use futures::future::ok;
+use rand::random;
+use tokio_core::io::Io;
+
+use date;
+use enums::{Method, Status};
+use super::{Error, Encoder, EncoderDone, Head, WaitFlush};
+use super::buffered::Request;
+use super::error::ErrorEnum;
+
+
+/// Size of the chunk read from the file at once
+const CHUNK_SIZE: usize = 65536;
+/// Maximum number of ranges in a single request we're willing to serve
+const MAX_RANGES: usize = 16;
+
+const MIME_TYPES: &'static [(&'static str, &'static str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "application/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "text/xml; charset=utf-8"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("ico", "image/x-icon"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Response future returned by `Files::serve`
+pub type ResponseFuture<S> = Box<Future<Item=EncoderDone<S>, Error=Error>>;
+
+/// Serves files from the root directory
+#[derive(Debug, Clone)]
+pub struct Files {
+    root: PathBuf,
+    index_file: Option<String>,
+}
+
+/// Fields of the request needed to serve a file
+///
+/// This is needed because `Head` is not available when response is
+/// started.
+#[derive(Debug, Clone)]
+pub struct FileRequest {
+    method: Method,
+    raw_path: Option<String>,
+    path: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+/// A file which is resolved and checked, but the body is not sent yet
+struct Found {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    content_type: &'static str,
+    etag: String,
+    last_modified: String,
+    mtime: u64,
+}
+
+enum Part {
+    Bytes(Vec<u8>),
+    File(u64, u64),
+}
+
+enum State<S: Io> {
+    Write(Encoder<S>),
+    Flush(WaitFlush<S>),
+    Void,
+}
+
+/// Writes the response body from a file and in-memory parts
+struct FileBody<S: Io> {
+    file: File,
+    parts: VecDeque<Part>,
+    state: State<S>,
+}
+
+enum Ranges {
+    /// No (valid) `Range` header, serve the whole file
+    Full,
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+impl FileRequest {
+    /// Extract the fields from request headers
+    pub fn from_head(head: &Head) -> FileRequest {
+        FileRequest::new(head.method().clone(),
+            head.raw_path().map(|p| p.to_string()),
+            head.safe_path().map(|p| p.into_owned()),
+            head.headers())
+    }
+    /// Extract the fields from buffered request
+    pub fn from_request(req: &Request) -> FileRequest {
+        FileRequest::new(req.method().clone(),
+            Some(req.raw_path().to_string()),
+            req.safe_path().map(|p| p.into_owned()),
+            req.headers().iter().map(|&(ref k, ref v)| (&k[..], &v[..])))
+    }
+    fn new<'x, I>(method: Method, raw_path: Option<String>,
+        path: Option<String>, headers: I)
+        -> FileRequest
+        where I: Iterator<Item=(&'x str, &'x [u8])>
+    {
+        let mut req = FileRequest {
+            method: method,
+            raw_path: raw_path,
+            path: path,
+            if_none_match: None,
+            if_modified_since: None,
+            range: None,
+            if_range: None,
+        };
+        for (name, value) in headers {
+            let value = match from_utf8(value) {
+                Ok(value) => Some(value.trim().to_string()),
+                Err(_) => continue,
+            };
+            if name.eq_ignore_ascii_case("If-None-Match") {
+                req.if_none_match = value;
+            } else if name.eq_ignore_ascii_case("If-Modified-Since") {
+                req.if_modified_since = value;
+            } else if name.eq_ignore_ascii_case("Range") {
+                req.range = value;
+            } else if name.eq_ignore_ascii_case("If-Range") {
+                req.if_range = value;
+            }
+        }
+        return req;
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext,
+        None => return "application/octet-stream",
+    };
+    MIME_TYPES.iter()
+        .find(|&&(e, _)| e.eq_ignore_ascii_case(ext))
+        .map(|&(_, ctype)| ctype)
+        .unwrap_or("application/octet-stream")
+}
+
+fn etag_matches(header: &str, etag: &str) -> bool {
+    // Weak comparison, as required for `If-None-Match`
+    let etag = etag.trim_left_matches("W/");
+    header.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_left_matches("W/") == etag
+    })
+}
+
+/// Parses `Range` header
+///
+/// Invalid (or unsupported) header is ignored as required by the spec
+fn parse_ranges(header: &str, len: u64) -> Ranges {
+    let specs = if header.starts_with("bytes=") {
+        &header["bytes=".len()..]
+    } else {
+        return Ranges::Full;
+    };
+    let mut result = Vec::new();
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.len() == 0 {
+            continue;
+        }
+        let dash = match spec.find('-') {
+            Some(dash) => dash,
+            None => return Ranges::Full,
+        };
+        let (start, end) = (spec[..dash].trim(), spec[dash+1..].trim());
+        let range = if start.len() == 0 {
+            // Suffix range: last N bytes
+            match end.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some((len.saturating_sub(n), len - 1)),
+                Ok(_) => None,
+                Err(_) => return Ranges::Full,
+            }
+        } else {
+            let start = match start.parse::<u64>() {
+                Ok(start) => start,
+                Err(_) => return Ranges::Full,
+            };
+            let end = if end.len() == 0 {
+                len.saturating_sub(1)
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return Ranges::Full,
+                }
+            };
+            if start < len {
+                Some((start, ::std::cmp::min(end, len - 1)))
+            } else {
+                None
+            }
+        };
+        if let Some(range) = range {
+            result.push(range);
+        }
+    }
+    if result.len() > MAX_RANGES {
+        // Too many ranges is likely an attack, just serve the whole file
+        return Ranges::Full;
+    }
+    if result.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Partial(result)
+    }
+}
+
+impl Files {
+    /// Serve files from the directory
+    ///
+    /// `index.html` is served for directories by default.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Files {
+        Files {
+            root: root.into(),
+            index_file: Some("index.html".to_string()),
+        }
+    }
+    /// Set name of the file served for directory, `None` disables it
+    pub fn index_file(&mut self, name: Option<&str>) -> &mut Self {
+        self.index_file = name.map(|x| x.to_string());
+        self
+    }
+    /// Resolve path of the request into file system path
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut result = self.root.clone();
+        for component in Path::new(path.trim_left_matches('/')).components()
+        {
+            match component {
+                Component::Normal(part) => result.push(part),
+                Component::CurDir => {}
+                // Just in case, `safe_path` shouldn't contain them
+                Component::ParentDir | Component::RootDir
+                | Component::Prefix(..) => return None,
+            }
+        }
+        Some(result)
+    }
+    /// Returns the path with symlinks resolved if it's inside of the root
+    fn contained(&self, path: &Path) -> Result<PathBuf, Status> {
+        let root = self.root.canonicalize().map_err(|e| status(&e))?;
+        let path = path.canonicalize().map_err(|e| status(&e))?;
+        if path.starts_with(&root) {
+            Ok(path)
+        } else {
+            Err(Status::NotFound)
+        }
+    }
+    /// Open the file, returns either a file or a status to respond with
+    fn open(&self, req: &FileRequest) -> Result<Found, Status> {
+        let path = match req.path {
+            Some(ref path) if !path.contains('\0') => path,
+            _ => return Err(Status::NotFound),
+        };
+        let mut fs_path = match self.resolve(path) {
+            Some(fs_path) => fs_path,
+            None => return Err(Status::NotFound),
+        };
+        let mut meta = metadata(&fs_path)?;
+        if meta.is_dir() {
+            match self.index_file {
+                Some(ref name) if path.ends_with('/') => {
+                    fs_path.push(name);
+                    meta = metadata(&fs_path)?;
+                }
+                Some(_) => return Err(Status::MovedPermanently),
+                None => return Err(Status::NotFound),
+            }
+        }
+        if !meta.is_file() {
+            return Err(Status::NotFound);
+        }
+        let real_path = self.contained(&fs_path)?;
+        let file = File::open(&real_path).map_err(|e| status(&e))?;
+        let mtime = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(Found {
+            content_type: content_type(&fs_path),
+            path: real_path,
+            file: file,
+            len: meta.len(),
+            etag: format!("\"{:x}-{:x}\"", mtime, meta.len()),
+            last_modified: date::format(UNIX_EPOCH +
+                ::std::time::Duration::from_secs(mtime)),
+            mtime: mtime,
+        })
+    }
+    /// Serve a file for the request
+    ///
+    /// Only `GET` and `HEAD` requests are allowed, for other methods
+    /// `405 Method Not Allowed` is returned.
+    pub fn serve<S: Io + 'static>(&self, req: &FileRequest, e: Encoder<S>)
+        -> ResponseFuture<S>
+    {
+        match self.open(req) {
+            Ok(found) => respond(req, found, e),
+            Err(status) => error_page(req, status, e),
+        }
+    }
+}
+
+fn metadata(path: &Path) -> Result<Metadata, Status> {
+    path.metadata().map_err(|e| status(&e))
+}
+
+fn status(err: &io::Error) -> Status {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        _ => Status::NotFound,
+    }
+}
+
+fn error_page<S: Io + 'static>(req: &FileRequest, status: Status,
+    mut e: Encoder<S>)
+    -> ResponseFuture<S>
+{
+    let status = match req.method {
+        Method::Get | Method::Head => status,
+        _ => Status::MethodNotAllowed,
+    };
+    let body = format!("{} {}\n", status.code(), status.reason());
+    e.status(status);
+    match status {
+        Status::MethodNotAllowed => {
+            e.add_header("Allow", "GET, HEAD").unwrap();
+        }
+        Status::MovedPermanently => {
+            // Only reachable for directories, so path is known to be valid
+            let path = req.raw_path.as_ref().unwrap();
+            e.format_header("Location", format_args!("{}/", path)).unwrap();
+        }
+        _ => {}
+    }
+    e.add_header("Content-Type", "text/plain").unwrap();
+    e.add_length(body.len() as u64).unwrap();
+    if e.done_headers().unwrap() {
+        e.write_body(body.as_bytes());
+    }
+    Box::new(ok(e.done()))
+}
+
+/// Returns true if the `If-Range` precondition allows to send ranges
+fn if_range(req: &FileRequest, found: &Found) -> bool {
+    match req.if_range {
+        None => true,
+        // Only strong comparison is allowed here
+        Some(ref value) if value.starts_with('"') => *value == found.etag,
+        Some(ref value) => date::parse(value)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() == found.mtime)
+            .unwrap_or(false),
+    }
+}
+
+fn not_modified(req: &FileRequest, found: &Found) -> bool {
+    if let Some(ref value) = req.if_none_match {
+        return etag_matches(value, &found.etag);
+    }
+    if let Some(ref value) = req.if_modified_since {
+        if let Some(since) = date::parse(value) {
+            let since = since.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs()).unwrap_or(0);
+            return found.mtime <= since;
+        }
+    }
+    return false;
+}
+
+fn respond<S: Io + 'static>(req: &FileRequest, found: Found,
+    mut e: Encoder<S>)
+    -> ResponseFuture<S>
+{
+    if req.method != Method::Get && req.method != Method::Head {
+        return error_page(req, Status::MethodNotAllowed, e);
+    }
+    let ranges = match req.range {
+        Some(ref value) if req.method == Method::Get => {
+            if if_range(req, &found) {
+                parse_ranges(value, found.len)
+            } else {
+                Ranges::Full
+            }
+        }
+        _ => Ranges::Full,
+    };
+    let not_modified = not_modified(req, &found);
+    if not_modified {
+        e.status(Status::NotModified);
+    } else {
+        match ranges {
+            Ranges::Full => e.status(Status::Ok),
+            Ranges::Partial(..) => e.status(Status::PartialContent),
+            Ranges::Unsatisfiable => {
+                e.status(Status::RequestRangeNotSatisfiable);
+                e.format_header("Content-Range",
+                    format_args!("bytes */{}", found.len)).unwrap();
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                return Box::new(ok(e.done()));
+            }
+        }
+    }
+    e.add_header("Last-Modified", &found.last_modified).unwrap();
+    e.add_header("ETag", &found.etag).unwrap();
+    e.add_header("Accept-Ranges", "bytes").unwrap();
+    if not_modified {
+        e.done_headers().unwrap();
+        return Box::new(ok(e.done()));
+    }
+    let mut parts = VecDeque::new();
+    match ranges {
+        Ranges::Full => {
+            e.add_header("Content-Type", found.content_type).unwrap();
+            e.add_length(found.len).unwrap();
+            parts.push_back(Part::File(0, found.len));
+        }
+        Ranges::Partial(ref ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            e.add_header("Content-Type", found.content_type).unwrap();
+            e.format_header("Content-Range",
+                format_args!("bytes {}-{}/{}", start, end, found.len))
+                .unwrap();
+            e.add_length(end - start + 1).unwrap();
+            parts.push_back(Part::File(start, end - start + 1));
+        }
+        Ranges::Partial(ref ranges) => {
+            let boundary = format!("{:016x}", random::<u64>());
+            let mut total = 0;
+            for &(start, end) in ranges {
+                let head = format!("\r\n--{}\r\nContent-Type: {}\r\n\
+                    Content-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, found.content_type, start, end, found.len);
+                total += head.len() as u64 + end - start + 1;
+                parts.push_back(Part::Bytes(head.into_bytes()));
+                parts.push_back(Part::File(start, end - start + 1));
+            }
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            total += tail.len() as u64;
+            parts.push_back(Part::Bytes(tail.into_bytes()));
+            e.format_header("Content-Type",
+                format_args!("multipart/byteranges; boundary={}", boundary))
+                .unwrap();
+            e.add_length(total).unwrap();
+        }
+        Ranges::Unsatisfiable => unreachable!(),
+    }
+    if !e.done_headers().unwrap() {
+        // HEAD request
+        return Box::new(ok(e.done()));
+    }
+    Box::new(FileBody {
+        file: found.file,
+        parts: parts,
+        state: State::Write(e),
+    })
+}
+
+impl<S: Io> FileBody<S> {
+    /// Writes next chunk to the encoder, returns false if nothing left
+    fn write_chunk(&mut self, e: &mut Encoder<S>) -> Result<bool, io::Error> {
+        let (start, len) = match self.parts.pop_front() {
+            Some(Part::Bytes(data)) => {
+                e.write_body(&data);
+                return Ok(true);
+            }
+            Some(Part::File(start, len)) => (start, len),
+            None => return Ok(false),
+        };
+        let chunk = ::std::cmp::min(len, CHUNK_SIZE as u64);
+        let mut buf = vec![0; chunk as usize];
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.read_exact(&mut buf)?;
+        e.write_body(&buf);
+        if chunk < len {
+            self.parts.push_front(Part::File(start + chunk, len - chunk));
+        }
+        Ok(true)
+    }
+}
+
+impl<S: Io> Future for FileBody<S> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        use self::State::*;
+        loop {
+            let next = match replace(&mut self.state, Void) {
+                Write(mut e) => {
+                    if self.write_chunk(&mut e).map_err(ErrorEnum::Io)? {
+                        Flush(e.wait_flush(CHUNK_SIZE))
+                    } else {
+                        return Ok(Async::Ready(e.done()));
+                    }
+                }
+                Flush(mut f) => match f.poll().map_err(ErrorEnum::Io)? {
+                    Async::Ready(e) => Write(e),
+                    Async::NotReady => {
+                        self.state = Flush(f);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Void => unreachable!(),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(feature="sendfile")]
+mod sendfile {
+    use std::os::unix::io::AsRawFd;
+
+    use futures::Future;
+    use tokio_core::io::Io;
+    use tk_sendfile::DiskPool;
+
+    use enums::{Method, Status};
+    use server::{Encoder, Error};
+    use server::error::ErrorEnum;
+    use super::{Files, FileRequest, ResponseFuture, Ranges};
+    use super::{respond, error_page, not_modified, parse_ranges, if_range};
+
+    impl Files {
+        /// Serve a file for the request using `sendfile` if possible
+        ///
+        /// Whole file responses to `GET` requests are sent with zero-copy
+        /// from the `pool` threads. Other responses (ranges, `HEAD`, not
+        /// modified) are the same as in `serve()`.
+        pub fn serve_sendfile<S>(&self, req: &FileRequest, mut e: Encoder<S>,
+            pool: &DiskPool)
+            -> ResponseFuture<S>
+            where S: Io + AsRawFd + Send + 'static,
+        {
+            let found = match self.open(req) {
+                Ok(found) => found,
+                Err(status) => return error_page(req, status, e),
+            };
+            let full = req.method == Method::Get && !not_modified(req, &found)
+                && match req.range {
+                    Some(ref value) if if_range(req, &found) => {
+                        matches!(parse_ranges(value, found.len), Ranges::Full)
+                    }
+                    _ => true,
+                };
+            if !full {
+                return respond(req, found, e);
+            }
+            e.status(Status::Ok);
+            e.add_header("Content-Type", found.content_type).unwrap();
+            e.add_header("Last-Modified", &found.last_modified).unwrap();
+            e.add_header("ETag", &found.etag).unwrap();
+            e.add_header("Accept-Ranges", "bytes").unwrap();
+            Box::new(pool.open(found.path)
+                .and_then(move |file| {
+                    // File might be changed since we've checked metadata,
+                    // but we've not sent anything yet
+                    e.add_length(file.size()).unwrap();
+                    e.done_headers().unwrap();
+                    e.raw_body()
+                    .and_then(|raw_body| file.write_into(raw_body))
+                    .map(|raw_body| raw_body.done())
+                })
+                .map_err(|err| Error::from(ErrorEnum::Io(err))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir, remove_dir_all};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use futures::Future;
+    use rand::random;
+    use tk_bufstream::{IoBuf, MockData};
+
+    use enums::{Method, Version};
+    use server::encoder::{self, ResponseConfig, get_inner};
+    use super::{Files, FileRequest};
+    use super::{parse_ranges, etag_matches, content_type, Ranges};
+
+    /// A temporary directory which is removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let path = temp_dir()
+                .join(format!("tk-http-files-{:016x}", random::<u64>()));
+            create_dir(&path).unwrap();
+            create_dir(path.join("root")).unwrap();
+            File::create(path.join("root/digits.txt")).unwrap()
+                .write_all(b"0123456789").unwrap();
+            File::create(path.join("secret.txt")).unwrap()
+                .write_all(b"secret").unwrap();
+            TempDir(path)
+        }
+        fn files(&self) -> Files {
+            Files::new(self.0.join("root"))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            remove_dir_all(&self.0).ok();
+        }
+    }
+
+    /// Returns response headers and body
+    fn get(files: &Files, path: &str, headers: &[(&str, &str)])
+        -> (String, String)
+    {
+        let req = FileRequest::new(Method::Get,
+            Some(path.to_string()), Some(path.to_string()),
+            headers.iter().map(|&(name, value)| (name, value.as_bytes())));
+        let mock = MockData::new();
+        let (output, _) = IoBuf::new(mock.clone()).split();
+        let e = encoder::new(output, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http11,
+            expect_continue: false,
+            continue_sent: false,
+        }, &Default::default());
+        let done = files.serve(&req, e).wait().unwrap();
+        get_inner(done).flush().unwrap();
+        let response = String::from_utf8(mock.output(..)).unwrap();
+        let end = response.find("\r\n\r\n").unwrap() + 4;
+        (response[..end].to_string(), response[end..].to_string())
+    }
+
+    fn header<'x>(head: &'x str, name: &str) -> &'x str {
+        head.lines()
+            .find(|line| line.starts_with(name))
+            .map(|line| line[name.len()+2..].trim())
+            .unwrap()
+    }
+
+    fn ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse_ranges(header, len) {
+            Ranges::Full => None,
+            Ranges::Partial(x) => Some(x),
+            Ranges::Unsatisfiable => Some(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(ranges("bytes=0-9", 100), Some(vec![(0, 9)]));
+        assert_eq!(ranges("bytes=90-", 100), Some(vec![(90, 99)]));
+        assert_eq!(ranges("bytes=90-200", 100), Some(vec![(90, 99)]));
+        assert_eq!(ranges("bytes=-10", 100), Some(vec![(90, 99)]));
+        assert_eq!(ranges("bytes=-200", 100), Some(vec![(0, 99)]));
+        assert_eq!(ranges("bytes=0-0, 5-6", 100),
+                   Some(vec![(0, 0), (5, 6)]));
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(ranges("bytes=100-", 100), Some(vec![]));
+        assert_eq!(ranges("bytes=-0", 100), Some(vec![]));
+        assert_eq!(ranges("bytes=0-", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn invalid_range() {
+        assert_eq!(ranges("items=0-9", 100), None);
+        assert_eq!(ranges("bytes=9-0", 100), None);
+        assert_eq!(ranges("bytes=x-1", 100), None);
+        assert_eq!(ranges("bytes=1", 100), None);
+        assert_eq!(ranges(&format!("bytes={}", vec!["1-2"; 20].join(",")),
+                          100), None);
+    }
+
+    #[test]
+    fn etag() {
+        assert!(etag_matches("\"a-1\"", "\"a-1\""));
+        assert!(etag_matches("\"x\", W/\"a-1\"", "\"a-1\""));
+        assert!(etag_matches("*", "\"a-1\""));
+        assert!(!etag_matches("\"a-2\"", "\"a-1\""));
+    }
+
+    #[test]
+    fn mime() {
+        assert_eq!(content_type(Path::new("a/index.HTML")),
+                   "text/html; charset=utf-8");
+        assert_eq!(content_type(Path::new("a.png")), "image/png");
+        assert_eq!(content_type(Path::new("Makefile")),
+                   "application/octet-stream");
+    }
+
+    #[test]
+    fn full_file() {
+        let dir = TempDir::new();
+        let (head, body) = get(&dir.files(), "/digits.txt", &[]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&head, "Content-Type"),
+                   "text/plain; charset=utf-8");
+        assert_eq!(header(&head, "Content-Length"), "10");
+        assert_eq!(body, "0123456789");
+    }
+
+    #[test]
+    fn not_modified() {
+        let dir = TempDir::new();
+        let files = dir.files();
+        let (head, _) = get(&files, "/digits.txt", &[]);
+        let etag = header(&head, "ETag");
+        let modified = header(&head, "Last-Modified");
+
+        let (head, body) = get(&files, "/digits.txt",
+                               &[("If-None-Match", etag)]);
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(body, "");
+        let (head, _) = get(&files, "/digits.txt",
+                            &[("If-None-Match", "\"other\"")]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let (head, body) = get(&files, "/digits.txt",
+                               &[("If-Modified-Since", modified)]);
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(body, "");
+        let (head, _) = get(&files, "/digits.txt",
+            &[("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn partial_content() {
+        let dir = TempDir::new();
+        let (head, body) = get(&dir.files(), "/digits.txt",
+                               &[("Range", "bytes=2-4")]);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header(&head, "Content-Range"), "bytes 2-4/10");
+        assert_eq!(header(&head, "Content-Length"), "3");
+        assert_eq!(body, "234");
+    }
+
+    #[test]
+    fn range_not_satisfiable() {
+        let dir = TempDir::new();
+        let (head, body) = get(&dir.files(), "/digits.txt",
+                               &[("Range", "bytes=10-")]);
+        assert!(head.starts_with(
+            "HTTP/1.1 416 Request Range Not Satisfiable\r\n"));
+        assert_eq!(header(&head, "Content-Range"), "bytes */10");
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        let dir = TempDir::new();
+        let (head, body) = get(&dir.files(), "/digits.txt",
+                               &[("Range", "bytes=0-1,-2")]);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        let ctype = header(&head, "Content-Type");
+        let prefix = "multipart/byteranges; boundary=";
+        assert!(ctype.starts_with(prefix));
+        let boundary = &ctype[prefix.len()..];
+        assert_eq!(header(&head, "Content-Length"),
+                   body.len().to_string());
+        assert_eq!(body, format!(
+            "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{0}--\r\n", boundary));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_outside_root() {
+        use std::os::unix::fs::symlink;
+        let dir = TempDir::new();
+        symlink(dir.0.join("secret.txt"), dir.0.join("root/secret.txt"))
+            .unwrap();
+        symlink(dir.0.join("root/digits.txt"), dir.0.join("root/link.txt"))
+            .unwrap();
+        let (head, _) = get(&dir.files(), "/secret.txt", &[]);
+        assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let (head, body) = get(&dir.files(), "/link.txt", &[]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body, "0123456789");
+    }
+}
@@ -6,6 +6,7 @@ use std::borrow::Cow;
 use httparse::{self, EMPTY_HEADER, Request, Header};
 use tokio_core::io::Io;
 use tk_bufstream::Buf;
+use url::form_urlencoded;
 
 use server::error::{Error, ErrorEnum};
 use super::{RequestTarget, Dispatcher};
@@ -13,8 +14,10 @@ use super::codec::BodyKind;
 use super::encoder::ResponseConfig;
 use super::websocket::{self, WebsocketHandshake};
 use super::request_target;
+use super::path;
 use headers;
-use {Version};
+use cookie::{self, Cookies};
+use {Version, Method};
 
 
 /// Number of headers to allocate on a stack
@@ -43,7 +46,8 @@ struct RequestConfig<'a> {
 /// discard any needed fields and headers from it.
 #[derive(Debug)]
 pub struct Head<'a> {
-    method: &'a str,
+    method: Method,
+    raw_method: &'a str,
     raw_target: &'a str,
     target: RequestTarget<'a>,
     host: Option<&'a str>,
@@ -51,6 +55,7 @@ pub struct Head<'a> {
     version: Version,
     headers: &'a [Header<'a>],
     body_kind: BodyKind,
+    expect_continue: bool,
     connection_close: bool,
     connection_header: Option<Cow<'a, str>>,
 }
@@ -68,8 +73,12 @@ pub struct HeaderIter<'a> {
 
 impl<'a> Head<'a> {
     /// Returns a HTTP method
-    pub fn method(&self) -> &str {
-        self.method
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+    /// Returns a HTTP method as string, exactly as it's sent by client
+    pub fn raw_method(&self) -> &str {
+        self.raw_method
     }
     /// Request-target (the middle part of the first line of request)
     pub fn request_target(&self) -> &RequestTarget<'a> {
@@ -94,6 +103,38 @@ impl<'a> Head<'a> {
             Asterisk => None,
         }
     }
+    /// Returns path portion of request uri without query string
+    ///
+    /// Path is not percent-decoded.
+    pub fn raw_path(&self) -> Option<&str> {
+        self.path().map(|p| path::split_query(p).0)
+    }
+    /// Returns query string of request uri (the part after `?`)
+    pub fn query(&self) -> Option<&str> {
+        self.path().and_then(|p| path::split_query(p).1)
+    }
+    /// Returns percent-decoded path without query string
+    ///
+    /// Returns `None` if path is not a valid utf-8 after decoding (or
+    /// request target doesn't contain path at all).
+    ///
+    /// Note: decoded path may contain `..` segments, so don't use it for
+    /// looking up files, see `safe_path()` instead.
+    pub fn decoded_path(&self) -> Option<Cow<str>> {
+        self.raw_path().and_then(path::decode)
+    }
+    /// Returns percent-decoded path without query string, if it's safe
+    ///
+    /// Same as `decoded_path()` but also returns `None` if path contains
+    /// `..` segments or encoded slashes (`%2F` or `%5C`), i.e. anything that
+    /// might be used to escape the root directory.
+    pub fn safe_path(&self) -> Option<Cow<str>> {
+        self.raw_path().and_then(path::decode_safe)
+    }
+    /// Iterates over decoded key-value pairs of the query string
+    pub fn query_pairs(&self) -> form_urlencoded::Parse {
+        path::query_pairs(self.query())
+    }
     /// Return host of a request
     ///
     /// Note: this might be extracted from request-target portion of
@@ -145,6 +186,10 @@ impl<'a> Head<'a> {
             iter: self.headers.iter(),
         }
     }
+    /// Iterator over name-value pairs of all `Cookie` headers
+    pub fn cookies(&self) -> Cookies {
+        cookie::cookies(self.headers())
+    }
     /// All headers of HTTP request
     ///
     /// Unlike `self.headers()` this does include hop-by-hop headers. This
@@ -181,6 +226,20 @@ impl<'a> Head<'a> {
             _ => None,
         }
     }
+    /// Returns true if request contains `Expect: 100-continue` header
+    ///
+    /// The protocol sends `100 Continue` by itself when codec chooses
+    /// to read the request body (i.e. after `Codec::recv_mode()` is called),
+    /// so you don't need to do anything special in most cases. But if you
+    /// want to reply with a final status (for example `417 Expectation
+    /// Failed` or `413 Payload Too Large`) without receiving the body, return
+    /// `RecvMode::reject()` from the codec.
+    ///
+    /// Note: the expectation is ignored for HTTP/1.0 requests and for
+    /// requests without body, as specification requires.
+    pub fn expect_continue(&self) -> bool {
+        self.expect_continue
+    }
     /// Check if connection is a websocket and return hanshake info
     ///
     /// `Err(())` is returned when there was handshake but where was something
@@ -324,7 +383,8 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
                 let cfg = scan_headers(&raw)?;
                 let ver = raw.version.unwrap();
                 let head = Head {
-                    method: raw.method.unwrap(),
+                    method: Method::from(raw.method.unwrap()),
+                    raw_method: raw.method.unwrap(),
                     raw_target: raw.path.unwrap(),
                     target: cfg.target,
                     version: if ver == 1
@@ -333,6 +393,7 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
                     conflicting_host: cfg.conflicting_host,
                     headers: raw.headers,
                     body_kind: cfg.body,
+                    expect_continue: cfg.expect_continue,
                     // For HTTP/1.0 we could implement
                     // Connection: Keep-Alive but hopefully it's rare
                     // enough to ignore nowadays
@@ -340,7 +401,6 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
                     connection_header: cfg.connection,
                 };
                 let codec = disp.headers_received(&head)?;
-                // TODO(tailhook) send 100-expect response headers
                 let response_config = ResponseConfig::from(&head);
                 (cfg.body, codec, response_config, bytes)
             }
@@ -9,12 +9,22 @@ mod request_target;
 mod headers;
 mod websocket;
 mod recv_mode;
+mod shutdown;
+mod serve;
+mod path;
 pub mod buffered;
+pub mod sse;
+pub mod router;
+pub mod files;
+pub mod compress;
 
 pub use self::error::Error;
 pub use self::encoder::{Encoder, EncoderDone, FutureRawBody, RawBody};
+pub use self::encoder::WaitFlush;
 pub use self::codec::{Codec, Dispatcher};
 pub use self::proto::Proto;
+pub use self::shutdown::Shutdown;
+pub use self::serve::{serve, Serve};
 pub use self::headers::{Head, HeaderIter};
 pub use self::request_target::RequestTarget;
 pub use self::websocket::{WebsocketHandshake};
@@ -34,6 +44,8 @@ pub struct Config {
     input_body_whole_timeout: Duration,
     output_body_byte_timeout: Duration,
     output_body_whole_timeout: Duration,
+    max_connections: usize,
+    accept_error_delay: Duration,
 }
 
 /// This type is returned from `headers_received` handler of either
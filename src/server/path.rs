@@ -0,0 +1,85 @@
+use std::borrow::Cow;
+
+use url::form_urlencoded;
+use url::percent_encoding::percent_decode;
+
+
+/// Splits request path into path itself and a query string (without `?`)
+pub fn split_query(path: &str) -> (&str, Option<&str>) {
+    match path.find('?') {
+        Some(idx) => (&path[..idx], Some(&path[idx+1..])),
+        None => (path, None),
+    }
+}
+
+/// Percent-decodes the path, returns `None` if it's not valid utf-8
+pub fn decode(path: &str) -> Option<Cow<str>> {
+    percent_decode(path.as_bytes()).decode_utf8().ok()
+}
+
+/// Percent-decodes the path, rejecting the ones which may escape the root
+///
+/// Returns `None` if any segment is `..` (either literal or encoded) or
+/// contains encoded slash or backslash, or if path is not valid utf-8.
+pub fn decode_safe(path: &str) -> Option<Cow<str>> {
+    for segment in path.split('/') {
+        let segment = match percent_decode(segment.as_bytes()).decode_utf8() {
+            Ok(segment) => segment,
+            Err(_) => return None,
+        };
+        if segment == ".." || segment.contains('/')
+            || segment.contains('\\')
+        {
+            return None;
+        }
+    }
+    decode(path)
+}
+
+/// Parses query string into decoded key-value pairs
+pub fn query_pairs(query: Option<&str>) -> form_urlencoded::Parse {
+    form_urlencoded::parse(query.unwrap_or("").as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_query, decode, decode_safe, query_pairs};
+
+    #[test]
+    fn split() {
+        assert_eq!(split_query("/a/b"), ("/a/b", None));
+        assert_eq!(split_query("/a?x=1?2"), ("/a", Some("x=1?2")));
+        assert_eq!(split_query("/?"), ("/", Some("")));
+    }
+
+    #[test]
+    fn decode_path() {
+        assert_eq!(decode("/a%20b/c").unwrap(), "/a b/c");
+        assert_eq!(decode("/a%2Fb").unwrap(), "/a/b");
+        assert_eq!(decode("/%ff"), None);
+    }
+
+    #[test]
+    fn safe_path() {
+        assert_eq!(decode_safe("/a%20b/c").unwrap(), "/a b/c");
+        assert_eq!(decode_safe("/a/./b").unwrap(), "/a/./b");
+        assert_eq!(decode_safe("/a%2Fb"), None);
+        assert_eq!(decode_safe("/a%5c..%5cb"), None);
+        assert_eq!(decode_safe("/a/../b"), None);
+        assert_eq!(decode_safe("/a/%2e%2E/b"), None);
+        assert_eq!(decode_safe("/a/..b/c").unwrap(), "/a/..b/c");
+    }
+
+    #[test]
+    fn query() {
+        let pairs = query_pairs(Some("a=1&b=x+y&c=%26"))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "x y".to_string()),
+            ("c".to_string(), "&".to_string()),
+        ]);
+        assert_eq!(query_pairs(None).count(), 0);
+    }
+}
@@ -1,4 +1,6 @@
 use std::mem;
+use std::cmp::min;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::collections::VecDeque;
 use std::time::Instant;
@@ -8,14 +10,18 @@ use tk_bufstream::{IoBuf, WriteBuf, ReadBuf};
 use tokio_core::io::Io;
 use tokio_core::reactor::{Handle, Timeout};
 
-use super::encoder::{self, get_inner, ResponseConfig};
-use super::{Dispatcher, Codec, Config};
+use super::encoder::{self, get_inner, ResponseConfig, FlushProgress};
+use super::{Dispatcher, Codec, Config, Shutdown};
 use super::headers::parse_headers;
 use super::codec::BodyKind;
-use server::error::{ErrorEnum, Error};
+use server::error::{ErrorEnum, Error, response_status};
 use server::recv_mode::{Mode, get_mode};
 use chunked;
 use body_parser::BodyProgress;
+use enums::{Status, Version};
+
+
+const CONTINUE_LINE: &'static [u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
 
 
 enum OutState<S: Io, F, C> {
@@ -30,6 +36,9 @@ struct BodyState<C> {
     progress: BodyProgress,
     response_config: ResponseConfig,
     codec: C,
+    /// Only for `Progressive` mode: the response is started before the
+    /// whole request body is received
+    response_started: bool,
 }
 
 enum InState<C> {
@@ -48,9 +57,18 @@ pub struct PureProto<S: Io, D: Dispatcher<S>> {
     waiting: VecDeque<(ResponseConfig, D::Codec)>,
     writing: OutState<S, <D::Codec as Codec<S>>::ResponseFuture, D::Codec>,
     config: Arc<Config>,
+    /// Response to write when request can't be parsed
+    error_response: Option<(Status, ResponseConfig)>,
+    /// Error to return after error response is written
+    error: Option<Error>,
+    /// No new requests are read after graceful shutdown is started
+    shutting_down: bool,
 
     last_byte_read: Instant,
     last_byte_written: Instant,
+    /// Shared with encoders to track bytes flushed in `wait_flush`
+    progress: Rc<FlushProgress>,
+    bytes_flushed: usize,
     /// Long-term deadline for reading (headers- or input body_whole- timeout)
     read_deadline: Instant,
     response_deadline: Instant,
@@ -61,6 +79,7 @@ pub struct Proto<S: Io, D: Dispatcher<S>> {
     proto: PureProto<S, D>,
     handle: Handle,
     timeout: Timeout,
+    shutdown: Option<Shutdown>,
 }
 
 fn new_body(mode: BodyKind, recv_mode: Mode)
@@ -80,6 +99,23 @@ fn new_body(mode: BodyKind, recv_mode: Mode)
     }
 }
 
+/// Returns status and config of the error response if it should be sent
+///
+/// Otherwise, the error itself is returned back
+fn error_response(err: Error, cfg: ResponseConfig)
+    -> Result<(Status, ResponseConfig, Error), Error>
+{
+    match response_status(&err) {
+        Some(status) => {
+            let mut cfg = cfg;
+            // We can't read next request reliably after the error
+            cfg.do_close = true;
+            Ok((status, cfg, err))
+        }
+        None => Err(err),
+    }
+}
+
 impl<S: Io, D: Dispatcher<S>> Proto<S, D> {
     /// Create a new protocol implementation from a TCP connection and a config
     ///
@@ -93,8 +129,22 @@ impl<S: Io, D: Dispatcher<S>> Proto<S, D> {
             handle: handle.clone(),
             timeout: Timeout::new(cfg.first_byte_timeout, handle)
                 .expect("can always add a timeout"),
+            shutdown: None,
         }
     }
+    /// Create a protocol implementation which can be shut down gracefully
+    ///
+    /// When `shutdown` is triggered, connection stops reading new requests,
+    /// finishes requests that are already read (the last response gets
+    /// `Connection: close`) and then the future resolves.
+    pub fn with_shutdown(conn: S, cfg: &Arc<Config>, dispatcher: D,
+        handle: &Handle, shutdown: Shutdown)
+        -> Proto<S, D>
+    {
+        let mut proto = Proto::new(conn, cfg, dispatcher, handle);
+        proto.shutdown = Some(shutdown);
+        return proto;
+    }
 }
 
 impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
@@ -110,9 +160,14 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
                 cfg.inflight_request_prealloc),
             writing: OutState::Idle(cout),
             config: cfg.clone(),
+            error_response: None,
+            error: None,
+            shutting_down: false,
 
             last_byte_read: Instant::now(),
             last_byte_written: Instant::now(),
+            progress: Rc::new(FlushProgress::default()),
+            bytes_flushed: 0,
             read_deadline: Instant::now() + cfg.first_byte_timeout,
             response_deadline: Instant::now(),  // irrelevant at start
         }
@@ -151,24 +206,61 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
                 Connected => (Connected, false),
                 KeepAlive => (KeepAlive, false),
                 Headers => {
-                    match parse_headers(&mut inbuf.in_buf,
-                                        &mut self.dispatcher)?
+                    let parsed = match parse_headers(&mut inbuf.in_buf,
+                                                     &mut self.dispatcher)
                     {
-                        Some((body, mut codec, cfg)) => {
+                        Ok(parsed) => parsed,
+                        Err(e) => {
+                            // Nothing is known about the request yet
+                            let cfg = ResponseConfig {
+                                is_head: false,
+                                do_close: true,
+                                version: Version::Http11,
+                                expect_continue: false,
+                                continue_sent: false,
+                            };
+                            let (status, cfg, err) = error_response(e, cfg)?;
+                            self.error_response = Some((status, cfg));
+                            self.error = Some(err);
+                            return Ok(true);
+                        }
+                    };
+                    match parsed {
+                        Some((body, mut codec, mut cfg)) => {
                             changed = true;
                             let mode = codec.recv_mode();
                             if get_mode(&mode) == Mode::Hijack {
                                 self.waiting.push_back((cfg, codec));
                                 (Hijack, true)
+                            } else if get_mode(&mode) == Mode::Reject {
+                                // Body is not read, so connection can't
+                                // be reused after the response
+                                cfg.do_close = true;
+                                self.waiting.push_back((cfg, codec));
+                                (Closed, true)
                             } else {
+                                let progress = match
+                                    new_body(body, get_mode(&mode))
+                                {
+                                    Ok(progress) => progress,
+                                    Err(e) => {
+                                        let (status, cfg, err) =
+                                            error_response(e.into(), cfg)?;
+                                        self.error_response =
+                                            Some((status, cfg));
+                                        self.error = Some(err);
+                                        return Ok(true);
+                                    }
+                                };
                                 let timeo = mode.timeout.unwrap_or(
                                     self.config.input_body_whole_timeout);
                                 self.read_deadline = Instant::now() + timeo;
                                 (Body(BodyState {
                                     mode: get_mode(&mode),
                                     response_config: cfg,
-                                    progress: new_body(body, get_mode(&mode))?,
-                                    codec: codec }),
+                                    progress: progress,
+                                    codec: codec,
+                                    response_started: false }),
                                  true)
                             }
                         }
@@ -176,8 +268,18 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
                     }
                 }
                 Body(mut body) => {
-                    body.progress.parse(inbuf)
-                        .map_err(ErrorEnum::ChunkParseError)?;
+                    if let Err(e) = body.progress.parse(inbuf) {
+                        let e: Error = ErrorEnum::from(e).into();
+                        if body.response_started {
+                            // Too late to send an error response
+                            return Err(e);
+                        }
+                        let (status, cfg, err) =
+                            error_response(e, body.response_config)?;
+                        self.error_response = Some((status, cfg));
+                        self.error = Some(err);
+                        return Ok(true);
+                    }
                     let (bytes, done) = body.progress.check_buf(inbuf);
                     let operation = if done {
                         Some(body.codec.data_received(
@@ -195,11 +297,19 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
                             body.progress.consume(inbuf, consumed);
                             if done && consumed == bytes {
                                 changed = true;
-                                self.waiting.push_back(
-                                    (body.response_config, body.codec));
+                                body.codec.trailers_received(
+                                    body.progress.trailers())?;
+                                if !body.response_started {
+                                    self.waiting.push_back(
+                                        (body.response_config, body.codec));
+                                }
                                 self.read_deadline = Instant::now()
                                     + self.config.keep_alive_timeout;
-                                (KeepAlive, true)
+                                if self.shutting_down {
+                                    (Closed, true)
+                                } else {
+                                    (KeepAlive, true)
+                                }
                             } else {
                                 (Body(body), true) // TODO(tailhook) check
                             }
@@ -242,43 +352,87 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
                     if let Some((rc, mut codec)) = self.waiting.pop_front() {
                         self.response_deadline = Instant::now()
                             + self.config.output_body_whole_timeout;
-                        let e = encoder::new(io, rc);
+                        self.last_byte_written = Instant::now();
+                        self.progress.waiting.set(false);
+                        let e = encoder::new(io, rc, &self.progress);
                         if matches!(self.reading, Hijack) {
                             (Switch(codec.start_response(e), codec), true)
                         } else {
                             (Write(codec.start_response(e)), true)
                         }
-                    } else {
-                        match self.reading {
-                            Body(BodyState { mode: BufferedUpfront(..), ..})
-                            | Closed | Headers | Connected | KeepAlive
-                            => {
-                                (Idle(io), false)
+                    } else if let Body(ref mut body) = self.reading {
+                        match body.mode {
+                            _ if body.response_config.expect_continue => {
+                                // Client waits for us before sending body,
+                                // and there are no responses pending, so
+                                // it's safe to send interim response now
+                                body.response_config.expect_continue = false;
+                                body.response_config.continue_sent = true;
+                                io.out_buf.extend(CONTINUE_LINE);
+                                (Idle(io), true)
                             }
-                            Body(BodyState { mode: Mode::Hijack, ..}) => {
-                                unreachable!();
+                            BufferedUpfront(..) => (Idle(io), false),
+                            Progressive(_) if body.response_started => {
+                                (Idle(io), false)
                             }
-                            Body(BodyState {
-                                mode: Progressive(_),
-                                codec: ref mut _codec, ..})
-                            => {
+                            Progressive(_) => {
+                                // Request body is still streaming, but
+                                // there is nothing to write before this
+                                // response, so start it right now
+                                body.response_started = true;
                                 self.response_deadline = Instant::now()
                                     + self.config.output_body_whole_timeout;
-                                // TODO(tailhook) start writing now
-                                unimplemented!();
+                                self.last_byte_written = Instant::now();
+                                self.progress.waiting.set(false);
+                                let e = encoder::new(io,
+                                    body.response_config, &self.progress);
+                                (Write(body.codec.start_response(e)), true)
+                            }
+                            Mode::Hijack | Mode::Reject => unreachable!(),
+                        }
+                    } else if let Some((status, rc)) =
+                        self.error_response.take()
+                    {
+                        // All previous responses are already written
+                        let e = encoder::new(io, rc, &self.progress);
+                        let err = self.error.as_ref()
+                            .expect("error is set with error response");
+                        let done = self.dispatcher.bad_request(status, err, e);
+                        (Idle(get_inner(done)), true)
+                    } else {
+                        match self.reading {
+                            Closed | Headers | Connected | KeepAlive
+                            => {
+                                (Idle(io), false)
                             }
-                            Hijack => unreachable!(),
+                            Body(..) | Hijack => unreachable!(),
                         }
                     }
                 }
                 Write(mut f) => {
+                    let was_waiting = self.progress.waiting.get();
                     match f.poll()? {
                         Async::Ready(x) => {
-                            self.read_deadline = Instant::now()
-                                + self.config.keep_alive_timeout;
+                            // Request body might still be streaming when
+                            // progressive response is done, so keep the
+                            // deadline of the body in this case
+                            if !matches!(self.reading, Body(..)) {
+                                self.read_deadline = Instant::now()
+                                    + self.config.keep_alive_timeout;
+                            }
                             (Idle(get_inner(x)), true)
                         }
                         Async::NotReady => {
+                            // Byte timeout is counted either from the last
+                            // byte flushed or from the start of waiting,
+                            // as response might be idle before that
+                            let bytes = self.progress.bytes.get();
+                            let started = !was_waiting &&
+                                self.progress.waiting.get();
+                            if bytes != self.bytes_flushed || started {
+                                self.bytes_flushed = bytes;
+                                self.last_byte_written = Instant::now();
+                            }
                             (Write(f), false)
                         }
                     }
@@ -308,13 +462,50 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
 }
 
 impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
+    /// Starts graceful shutdown
+    ///
+    /// No more requests are read, and `Connection: close` is added to the
+    /// response for the last request read (if it isn't started yet)
+    fn shutdown(&mut self) {
+        use self::InState::*;
+        self.shutting_down = true;
+        let idle = match self.reading {
+            Connected | KeepAlive | Headers => true,
+            Body(ref mut body) => {
+                body.response_config.do_close = true;
+                false
+            }
+            Hijack | Closed => false,
+        };
+        if idle {
+            self.reading = Closed;
+            if let Some(&mut (ref mut cfg, _)) = self.waiting.back_mut() {
+                cfg.do_close = true;
+            }
+        }
+    }
     /// Does all needed processing and returns Ok(true) if connection is fine
     /// and Ok(false) if it needs to be closed
-    fn process(&mut self) -> Result<bool, Error> {
+    pub fn process(&mut self) -> Result<bool, Error> {
         self.do_writes()?;
         while self.do_reads()? {
             self.do_writes()?;
         }
+        if matches!(self.reading, InState::Closed) && self.waiting.len() == 0
+            && matches!(self.writing,
+                OutState::Idle(ref io) if io.out_buf.len() == 0)
+        {
+            // No more requests are read and everything is written
+            if let Some(err) = self.error.take() {
+                return Err(err);
+            }
+            return Ok(false);
+        }
+        if self.error.is_some() {
+            // Error response is not flushed yet, don't drop the connection
+            // even if client has closed its side of it
+            return Ok(true);
+        }
         if self.inbuf.as_ref().map(|x| x.done()).unwrap_or(true) {
             Ok(false)
         } else {
@@ -326,7 +517,15 @@ impl<S: Io, D: Dispatcher<S>> PureProto<S, D> {
 
         match self.writing {
             Idle(..) => {}
-            Write(..) => return Some(self.response_deadline),
+            Write(..) => {
+                if self.progress.waiting.get() {
+                    // Response waits for the client to read data
+                    let byte_deadline = self.last_byte_written
+                        + self.config.output_body_byte_timeout;
+                    return Some(min(byte_deadline, self.response_deadline));
+                }
+                return Some(self.response_deadline);
+            }
             Switch(..) => return None,  // TODO(tailhook) is it right?
             Void => return None,  // TODO(tailhook) is it reachable?
         }
@@ -343,6 +542,16 @@ impl<S: Io, D: Dispatcher<S>> Future for Proto<S, D> {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<(), Error> {
+        let stop = match self.shutdown {
+            Some(ref mut shutdown) => {
+                matches!(shutdown.poll(), Ok(Async::Ready(())))
+            }
+            None => false,
+        };
+        if stop {
+            self.shutdown = None;
+            self.proto.shutdown();
+        }
         match self.proto.process() {
             Ok(false) => Ok(Async::Ready(())),
             Ok(true) => {
@@ -374,14 +583,25 @@ impl<S: Io, D: Dispatcher<S>> Future for Proto<S, D> {
 
 #[cfg(test)]
 mod test {
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::cell::{Cell, RefCell};
+    use std::time::{Duration, Instant};
 
-    use futures::{Empty, Async, empty};
+    use futures::{Future, Empty, Async, Poll, empty};
+    use futures::executor::{spawn, Spawn, Unpark};
+    use futures::future::{FutureResult, ok, lazy};
     use tk_bufstream::{MockData, ReadBuf, WriteBuf};
+    use tokio_core::io::Io;
+    use tokio_core::reactor::Core;
 
-    use super::PureProto;
-    use server::{Config, Dispatcher, Codec};
-    use server::{Head, RecvMode, Error, Encoder, EncoderDone};
+    use super::{Proto, PureProto};
+    use server::{Config, Dispatcher, Codec, Shutdown};
+    use server::{Head, RecvMode, Error, Encoder, EncoderDone, WaitFlush};
+    use server::error::{ErrorEnum, response_status};
+    use enums::Status;
 
     struct MockDisp {
     }
@@ -442,4 +662,584 @@ mod test {
         mock.add_input("GET / TTMP/2.0\r\n\r\n");
         proto.process().unwrap();
     }
+
+    #[test]
+    fn bad_request_response() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), MockDisp {});
+        proto.process().unwrap();
+        mock.add_input("GET / HTTP/1.1\r\n\
+                        Content-Length: 0\r\n\
+                        Content-Length: 0\r\n\r\n");
+        let err = proto.process().unwrap_err();
+        assert_eq!(response_status(&err), Some(Status::BadRequest));
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\n\
+               Content-Length: 16\r\nConnection: close\r\n\r\n\
+               400 Bad Request\n"[..]);
+    }
+
+    #[test]
+    fn request_too_long_response() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), MockDisp {});
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.0\r\n\
+                        Content-Length: 2048\r\n\r\n");
+        proto.process().unwrap_err();
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.0 413 Request Entity Too Large\r\n\
+               Content-Type: text/plain\r\nContent-Length: 29\r\n\
+               Connection: close\r\n\r\n\
+               413 Request Entity Too Large\n"[..]);
+    }
+
+    struct CustomDisp;
+
+    impl Dispatcher<MockData> for CustomDisp {
+        type Codec = MockCodec;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(MockCodec {})
+        }
+        fn bad_request(&mut self, status: Status, _error: &Error,
+            mut e: Encoder<MockData>)
+            -> EncoderDone<MockData>
+        {
+            e.status(status);
+            e.add_length(4).unwrap();
+            e.done_headers().unwrap();
+            e.write_body(b"oops");
+            e.done()
+        }
+    }
+
+    #[test]
+    fn custom_error_page() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), CustomDisp);
+        proto.process().unwrap();
+        mock.add_input("GET / TTMP/2.0\r\n\r\n");
+        proto.process().unwrap_err();
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 400 Bad Request\r\nContent-Length: 4\r\n\
+               Connection: close\r\n\r\noops"[..]);
+    }
+
+    struct ProgressiveDisp {
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    struct ProgressiveCodec {
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Dispatcher<MockData> for ProgressiveDisp {
+        type Codec = ProgressiveCodec;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(ProgressiveCodec { received: self.received.clone() })
+        }
+    }
+
+    impl Codec<MockData> for ProgressiveCodec {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::progressive(1)
+        }
+        fn data_received(&mut self, data: &[u8], _end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            self.received.borrow_mut().extend(data);
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::Ok);
+            e.add_length(2).unwrap();
+            if e.done_headers().unwrap() {
+                e.write_body(b"ok");
+            }
+            ok(e.done())
+        }
+    }
+
+    #[test]
+    fn progressive_response_before_body() {
+        let mock = MockData::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()),
+            ProgressiveDisp { received: received.clone() });
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Content-Length: 10\r\n\r\n\
+                        hello");
+        proto.process().unwrap();
+        assert_eq!(&received.borrow()[..], b"hello");
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..]);
+        mock.add_input("world");
+        proto.process().unwrap();
+        assert_eq!(&received.borrow()[..], b"helloworld");
+        // response is not repeated when body is finished
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..]);
+    }
+
+    #[test]
+    fn progressive_pipelined_requests() {
+        let mock = MockData::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()),
+            ProgressiveDisp { received: received.clone() });
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Content-Length: 5\r\n\r\n\
+                        hello\
+                        POST / HTTP/1.1\r\n\
+                        Content-Length: 3\r\n\r\n\
+                        abc");
+        proto.process().unwrap();
+        assert_eq!(&received.borrow()[..], b"helloabc");
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok\
+               HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..]);
+    }
+
+    struct TrailersDisp {
+        trailers: Rc<RefCell<Vec<(String, Vec<u8>)>>>,
+    }
+
+    struct TrailersCodec {
+        trailers: Rc<RefCell<Vec<(String, Vec<u8>)>>>,
+    }
+
+    impl Dispatcher<MockData> for TrailersDisp {
+        type Codec = TrailersCodec;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(TrailersCodec { trailers: self.trailers.clone() })
+        }
+    }
+
+    impl Codec<MockData> for TrailersCodec {
+        type ResponseFuture = Empty<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(1024)
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            assert_eq!(data, b"hello");
+            assert_eq!(self.trailers.borrow().len(), 0);
+            Ok(Async::Ready(data.len()))
+        }
+        fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+            -> Result<(), Error>
+        {
+            self.trailers.borrow_mut().extend(trailers.iter().cloned());
+            Ok(())
+        }
+        fn start_response(&mut self, _e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            empty()
+        }
+    }
+
+    #[test]
+    fn request_trailers() {
+        let mock = MockData::new();
+        let trailers = Rc::new(RefCell::new(Vec::new()));
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()),
+            TrailersDisp { trailers: trailers.clone() });
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n\
+                        5\r\nhello\r\n0\r\n\
+                        Checksum: 1234\r\n");
+        proto.process().unwrap();
+        assert_eq!(trailers.borrow().len(), 0);
+        mock.add_input("\r\n");
+        proto.process().unwrap();
+        assert_eq!(&trailers.borrow()[..],
+                   &[("Checksum".to_string(), b"1234".to_vec())][..]);
+    }
+
+    struct StreamingDisp;
+
+    struct StreamingCodec;
+
+    impl Dispatcher<MockData> for StreamingDisp {
+        type Codec = StreamingCodec;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(StreamingCodec)
+        }
+    }
+
+    impl Codec<MockData> for StreamingCodec {
+        type ResponseFuture = Box<Future<Item=EncoderDone<MockData>,
+                                         Error=Error>>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(1024)
+        }
+        fn data_received(&mut self, data: &[u8], _end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::Ok);
+            e.add_chunked().unwrap();
+            e.done_headers().unwrap();
+            e.write_body(b"hello");
+            Box::new(e.wait_flush(0)
+                .map_err(|e| ErrorEnum::Io(e).into())
+                .map(|mut e| {
+                    assert_eq!(e.bytes_buffered(), 0);
+                    e.write_body(b"world");
+                    e.done()
+                }))
+        }
+    }
+
+    #[test]
+    fn wait_flush() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), StreamingDisp);
+        proto.process().unwrap();
+        mock.add_input("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn shutdown_idle() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), StreamingDisp);
+        assert_eq!(proto.process().unwrap(), true);
+        proto.shutdown();
+        assert_eq!(proto.process().unwrap(), false);
+        assert_eq!(mock.output(..).len(), 0);
+    }
+
+    #[test]
+    fn shutdown_finishes_request() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), StreamingDisp);
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                        Content-Length: 5\r\n\r\nhel");
+        assert_eq!(proto.process().unwrap(), true);
+        proto.shutdown();
+        assert_eq!(proto.process().unwrap(), true);
+        mock.add_input("loGET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(proto.process().unwrap(), false);
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+               Connection: close\r\n\r\n\
+               5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"[..]);
+    }
+
+    struct Counter(AtomicUsize);
+
+    impl Unpark for Counter {
+        fn unpark(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn shutdown_proto(lp: &Core, shutdown: &Shutdown)
+        -> Spawn<Proto<MockData, StreamingDisp>>
+    {
+        spawn(Proto::with_shutdown(MockData::new(),
+            &Arc::new(Config::new()), StreamingDisp, &lp.handle(),
+            shutdown.clone()))
+    }
+
+    #[test]
+    fn shutdown_trigger_wakes_connection() {
+        let mut lp = Core::new().unwrap();
+        let shutdown = Shutdown::new();
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut proto = shutdown_proto(&lp, &shutdown);
+        lp.run(lazy(|| -> Result<(), ()> {
+            assert!(proto.poll_future(counter.clone()).unwrap()
+                .is_not_ready());
+            assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+            shutdown.trigger();
+            assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+            assert!(proto.poll_future(counter.clone()).unwrap().is_ready());
+            Ok(())
+        })).unwrap();
+    }
+
+    #[test]
+    fn shutdown_dropped_connection() {
+        let mut lp = Core::new().unwrap();
+        let shutdown = Shutdown::new();
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut proto = shutdown_proto(&lp, &shutdown);
+        lp.run(lazy(|| -> Result<(), ()> {
+            assert!(proto.poll_future(counter.clone()).unwrap()
+                .is_not_ready());
+            Ok(())
+        })).unwrap();
+        drop(proto);
+        shutdown.trigger();
+        // task of the dropped connection is not woken up
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    }
+
+    struct RejectDisp;
+
+    struct RejectCodec;
+
+    impl Dispatcher<MockData> for RejectDisp {
+        type Codec = RejectCodec;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            assert!(headers.expect_continue());
+            Ok(RejectCodec)
+        }
+    }
+
+    impl Codec<MockData> for RejectCodec {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::reject()
+        }
+        fn data_received(&mut self, _data: &[u8], _end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            unreachable!();
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::ExpectationFailed);
+            e.add_length(0).unwrap();
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+    }
+
+    #[test]
+    fn expect_continue() {
+        let mock = MockData::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()),
+            ProgressiveDisp { received: received.clone() });
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Content-Length: 5\r\n\
+                        Expect: 100-continue\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 100 Continue\r\n\r\n\
+               HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..]);
+        mock.add_input("hello");
+        proto.process().unwrap();
+        assert_eq!(&received.borrow()[..], b"hello");
+    }
+
+    #[test]
+    fn no_continue_for_http10() {
+        let mock = MockData::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()),
+            ProgressiveDisp { received: received.clone() });
+        proto.process().unwrap();
+        mock.add_input("POST / HTTP/1.0\r\n\
+                        Content-Length: 5\r\n\
+                        Expect: 100-continue\r\n\r\n");
+        proto.process().unwrap();
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\
+               Connection: close\r\n\r\nok"[..]);
+    }
+
+    #[test]
+    fn reject_expectation() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), RejectDisp);
+        assert_eq!(proto.process().unwrap(), true);
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Content-Length: 1000000\r\n\
+                        Expect: 100-continue\r\n\r\n");
+        assert_eq!(proto.process().unwrap(), false);
+        assert_eq!(&mock.output(..)[..],
+            &b"HTTP/1.1 417 Expectation Failed\r\nContent-Length: 0\r\n\
+               Connection: close\r\n\r\n"[..]);
+    }
+
+    /// A transport which may be switched to not accept writes
+    #[derive(Clone)]
+    struct Blocking {
+        mock: MockData,
+        blocked: Rc<Cell<bool>>,
+    }
+
+    impl Read for Blocking {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.mock.read(buf)
+        }
+    }
+
+    impl Write for Blocking {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocked.get() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.mock.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Io for Blocking {}
+
+    /// Starts response, but writes body only when `go` is set
+    struct IdleDisp {
+        go: Rc<Cell<bool>>,
+    }
+
+    struct IdleResponse {
+        go: Rc<Cell<bool>>,
+        encoder: Option<Encoder<Blocking>>,
+        flush: Option<WaitFlush<Blocking>>,
+    }
+
+    impl Dispatcher<Blocking> for IdleDisp {
+        type Codec = IdleDisp;
+
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            Ok(IdleDisp { go: self.go.clone() })
+        }
+    }
+
+    impl Codec<Blocking> for IdleDisp {
+        type ResponseFuture = IdleResponse;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(0)
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<Blocking>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::Ok);
+            e.add_chunked().unwrap();
+            e.done_headers().unwrap();
+            IdleResponse {
+                go: self.go.clone(),
+                encoder: Some(e),
+                flush: None,
+            }
+        }
+    }
+
+    impl Future for IdleResponse {
+        type Item = EncoderDone<Blocking>;
+        type Error = Error;
+        fn poll(&mut self) -> Poll<EncoderDone<Blocking>, Error> {
+            if !self.go.get() {
+                return Ok(Async::NotReady);
+            }
+            if let Some(mut e) = self.encoder.take() {
+                e.write_body(b"hello");
+                self.flush = Some(e.wait_flush(0));
+            }
+            let flush = self.flush.as_mut().unwrap();
+            match flush.poll().map_err(ErrorEnum::Io)? {
+                Async::Ready(e) => Ok(Async::Ready(e.done())),
+                Async::NotReady => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn idle_response() -> (Blocking, Rc<Cell<bool>>,
+                           PureProto<Blocking, IdleDisp>)
+    {
+        let io = Blocking {
+            mock: MockData::new(),
+            blocked: Rc::new(Cell::new(false)),
+        };
+        let go = Rc::new(Cell::new(false));
+        let mut proto = PureProto::new(io.clone(),
+            &Config::new()
+                .output_body_byte_timeout(Duration::new(15, 0))
+                .output_body_whole_timeout(Duration::new(3600, 0))
+                .done(),
+            IdleDisp { go: go.clone() });
+        proto.process().unwrap();
+        io.mock.add_input("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        proto.process().unwrap();
+        assert!(!proto.progress.waiting.get());
+        // response is idle for longer than byte timeout
+        proto.last_byte_written = Instant::now() - Duration::new(30, 0);
+        io.blocked.set(true);
+        go.set(true);
+        proto.process().unwrap();
+        assert!(proto.progress.waiting.get());
+        (io, go, proto)
+    }
+
+    #[test]
+    fn byte_timeout_starts_on_wait() {
+        let (io, _, mut proto) = idle_response();
+        let deadline = proto.timeout().unwrap();
+        assert!(deadline > Instant::now() + Duration::new(14, 0));
+        io.blocked.set(false);
+        assert_eq!(proto.process().unwrap(), true);
+        assert!(!proto.progress.waiting.get());
+        assert!(io.mock.output(..)
+            .ends_with(b"5\r\nhello\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn byte_timeout() {
+        let (_, _, mut proto) = idle_response();
+        // client doesn't read anything for too long
+        proto.last_byte_written = Instant::now() - Duration::new(30, 0);
+        proto.process().unwrap();
+        assert!(proto.progress.waiting.get());
+        assert!(proto.timeout().unwrap() <= Instant::now());
+    }
 }
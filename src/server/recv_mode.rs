@@ -8,6 +8,7 @@ pub enum Mode {
     BufferedUpfront(usize),
     Progressive(usize),
     Hijack,
+    Reject,
 }
 
 impl RecvMode {
@@ -53,6 +54,22 @@ impl RecvMode {
     pub fn hijack() -> RecvMode {
         RecvMode { mode: Mode::Hijack, timeout: None }
     }
+    /// Don't read request body, send the response and close the connection
+    ///
+    /// This is useful to reject the request early, for example by sending
+    /// `417 Expectation Failed` or `413 Payload Too Large` to the request
+    /// with `Expect: 100-continue` header, so that client doesn't upload
+    /// the body at all. `100 Continue` is never sent in this mode.
+    ///
+    /// The `Connection: close` header is added to the response
+    /// automatically, because the body of the request is not read and
+    /// connection can't be reused.
+    ///
+    /// Note: `data_received` method of Codec is never called for rejected
+    /// requests.
+    pub fn reject() -> RecvMode {
+        RecvMode { mode: Mode::Reject, timeout: None }
+    }
 
     /// Change timeout for reading the whole request body to this value
     /// instead of configured default
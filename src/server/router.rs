@@ -0,0 +1,462 @@
+//! A dispatcher that routes requests by method and path
+//!
+//! Patterns are matched segment by segment:
+//!
+//! * `/users` matches exactly this path
+//! * `/users/:id` matches any single non-empty segment and stores it as
+//!   `id` parameter
+//! * `/static/*path` matches the rest of the path (possibly empty), it's
+//!   allowed only as the last segment
+//!
+//! Query string is ignored when matching. Routes are tried in order they
+//! are added. `HEAD` requests are served by `GET` routes unless there is
+//! an explicit `HEAD` route.
+//!
+//! When no route matches the path, `404 Not Found` is returned. When path
+//! matches but method doesn't, `405 Method Not Allowed` is returned with
+//! the `Allow` header.
+//!
+use std::rc::Rc;
+
+use futures::{Async, Future};
+use futures::future::ok;
+use tokio_core::io::Io;
+use tk_bufstream::{ReadBuf, WriteBuf};
+
+use enums::{Method, Status};
+use super::{Error, Encoder, EncoderDone, Dispatcher, Codec, Head, RecvMode};
+
+
+/// A response future of codecs returned by the router
+pub type ResponseFuture<S> = Box<Future<Item=EncoderDone<S>, Error=Error>>;
+
+/// A codec returned by the router
+pub type BoxCodec<S> = Box<Codec<S, ResponseFuture=ResponseFuture<S>>>;
+
+type Factory<S> = Rc<Fn(&Head, &Params) -> Result<BoxCodec<S>, Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Tail(String),
+}
+
+struct Route<S: Io> {
+    method: Method,
+    pattern: Vec<Segment>,
+    factory: Factory<S>,
+}
+
+/// Parameters extracted from the path by the matching route
+#[derive(Debug, Clone)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+/// A dispatcher that sends each request to a codec factory of the route
+///
+/// Router is cheap to clone (factories are reference-counted), so it's
+/// usually built once and cloned for every connection.
+pub struct Router<S: Io> {
+    routes: Vec<Route<S>>,
+}
+
+/// Wraps any codec to make its response future boxed
+struct Boxed<C>(C);
+
+/// Codec that writes 404 and 405 responses
+struct ErrorPage {
+    status: Status,
+    allow: Option<String>,
+    has_body: bool,
+}
+
+impl Params {
+    /// Returns value of the named parameter
+    ///
+    /// Value is not percent-decoded.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+}
+
+impl<S: Io + 'static> Router<S> {
+    /// Create a router without routes (it returns 404 for everything)
+    pub fn new() -> Router<S> {
+        Router {
+            routes: Vec::new(),
+        }
+    }
+    /// Add a route
+    ///
+    /// The `factory` is called for every matching request, the codec
+    /// returned decides on `RecvMode` and writes the response.
+    ///
+    /// # Panics
+    ///
+    /// When pattern doesn't start with a slash or `*tail` segment is not
+    /// the last one.
+    pub fn route<F, C>(&mut self, method: Method, pattern: &str, factory: F)
+        -> &mut Self
+        where F: Fn(&Head, &Params) -> Result<C, Error> + 'static,
+              C: Codec<S> + 'static,
+    {
+        self.routes.push(Route {
+            method: method,
+            pattern: parse_pattern(pattern),
+            factory: Rc::new(move |head: &Head, params: &Params| {
+                factory(head, params)
+                    .map(|codec| Box::new(Boxed(codec)) as BoxCodec<S>)
+            }),
+        });
+        self
+    }
+}
+
+impl<S: Io> Clone for Router<S> {
+    fn clone(&self) -> Router<S> {
+        Router {
+            routes: self.routes.iter().map(|route| Route {
+                method: route.method.clone(),
+                pattern: route.pattern.clone(),
+                factory: route.factory.clone(),
+            }).collect(),
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(pattern.starts_with('/'),
+        "route pattern must start with a slash: {:?}", pattern);
+    let parts = pattern[1..].split('/').collect::<Vec<_>>();
+    let mut result = Vec::with_capacity(parts.len());
+    for (idx, part) in parts.iter().enumerate() {
+        if part.starts_with(':') {
+            result.push(Segment::Param(part[1..].to_string()));
+        } else if part.starts_with('*') {
+            assert!(idx == parts.len() - 1,
+                "tail segment must be the last one: {:?}", pattern);
+            result.push(Segment::Tail(part[1..].to_string()));
+        } else {
+            result.push(Segment::Literal(part.to_string()));
+        }
+    }
+    return result;
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    use self::Segment::*;
+    if !path.starts_with('/') {
+        return None;
+    }
+    let mut values = Vec::new();
+    let mut rest = Some(&path[1..]);
+    for segment in pattern {
+        let tail = match rest {
+            Some(tail) => tail,
+            None => return None,
+        };
+        if let Tail(ref name) = *segment {
+            values.push((name.clone(), tail.to_string()));
+            return Some(Params { values: values });
+        }
+        let part = match tail.find('/') {
+            Some(idx) => {
+                rest = Some(&tail[idx+1..]);
+                &tail[..idx]
+            }
+            None => {
+                rest = None;
+                tail
+            }
+        };
+        match *segment {
+            Literal(ref lit) if lit == part => {}
+            Param(ref name) if part.len() > 0 => {
+                values.push((name.clone(), part.to_string()));
+            }
+            _ => return None,
+        }
+    }
+    if rest.is_some() {
+        return None;
+    }
+    Some(Params { values: values })
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    let mut result = String::new();
+    for method in methods {
+        if !result.is_empty() {
+            result.push_str(", ");
+        }
+        result.push_str(method.as_str());
+    }
+    return result;
+}
+
+impl<S: Io + 'static> Dispatcher<S> for Router<S> {
+    type Codec = BoxCodec<S>;
+
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        let method = headers.method();
+        let path = headers.raw_path();
+        let mut fallback = None;
+        let mut allowed = Vec::new();
+        if let Some(path) = path {
+            for route in &self.routes {
+                let params = match match_path(&route.pattern, path) {
+                    Some(params) => params,
+                    None => continue,
+                };
+                if route.method == *method {
+                    return (route.factory)(headers, &params);
+                }
+                if !allowed.contains(&route.method) {
+                    allowed.push(route.method.clone());
+                }
+                if route.method == Method::Get {
+                    if !allowed.contains(&Method::Head) {
+                        allowed.push(Method::Head);
+                    }
+                    if *method == Method::Head && fallback.is_none() {
+                        fallback = Some((route.factory.clone(), params));
+                    }
+                }
+            }
+        }
+        if let Some((factory, params)) = fallback {
+            return factory(headers, &params);
+        }
+        let page = if allowed.is_empty() {
+            ErrorPage {
+                status: Status::NotFound,
+                allow: None,
+                has_body: headers.has_body(),
+            }
+        } else {
+            ErrorPage {
+                status: Status::MethodNotAllowed,
+                allow: Some(allow_header(&allowed)),
+                has_body: headers.has_body(),
+            }
+        };
+        Ok(Box::new(page) as BoxCodec<S>)
+    }
+}
+
+impl<S: Io + 'static, C: Codec<S>> Codec<S> for Boxed<C>
+    where C::ResponseFuture: 'static,
+{
+    type ResponseFuture = ResponseFuture<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.0.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.0.data_received(data, end)
+    }
+    fn trailers_received(&mut self, trailers: &[(String, Vec<u8>)])
+        -> Result<(), Error>
+    {
+        self.0.trailers_received(trailers)
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
+        Box::new(self.0.start_response(e))
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        self.0.hijack(output, input)
+    }
+}
+
+impl<S: Io + 'static> Codec<S> for ErrorPage {
+    type ResponseFuture = ResponseFuture<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        if self.has_body {
+            // Don't waste time reading the body nobody needs
+            RecvMode::reject()
+        } else {
+            RecvMode::buffered_upfront(0)
+        }
+    }
+    fn data_received(&mut self, data: &[u8], _end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<S>) -> Self::ResponseFuture {
+        let body = format!("{} {}\n",
+            self.status.code(), self.status.reason());
+        e.status(self.status);
+        if let Some(ref allow) = self.allow {
+            e.add_header("Allow", allow).unwrap();
+        }
+        e.add_header("Content-Type", "text/plain").unwrap();
+        e.add_length(body.len() as u64).unwrap();
+        if e.done_headers().unwrap() {
+            e.write_body(body.as_bytes());
+        }
+        Box::new(ok(e.done()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures::Async;
+    use futures::future::{FutureResult, ok};
+    use tk_bufstream::MockData;
+
+    use enums::{Method, Status};
+    use server::{Config, Codec, Encoder, EncoderDone, Error, RecvMode};
+    use server::proto::PureProto;
+    use super::{Router, parse_pattern, match_path, allow_header};
+
+    /// Responds with a fixed text
+    struct Text(String);
+
+    impl Codec<MockData> for Text {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(0)
+        }
+        fn data_received(&mut self, data: &[u8], _end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::Ok);
+            e.add_length(self.0.len() as u64).unwrap();
+            if e.done_headers().unwrap() {
+                e.write_body(self.0.as_bytes());
+            }
+            ok(e.done())
+        }
+    }
+
+    /// Sends a request through the router, returns response and whether
+    /// connection is kept alive
+    fn request(input: &str) -> (String, bool) {
+        let mut router = Router::new();
+        router.route(Method::Get, "/users/:id", |_, params| {
+            Ok(Text(params.get("id").unwrap().to_string()))
+        });
+        router.route(Method::Post, "/users", |_, _| {
+            Ok(Text("created".to_string()))
+        });
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Arc::new(Config::new()), router);
+        proto.process().unwrap();
+        mock.add_input(input);
+        let alive = proto.process().unwrap();
+        (String::from_utf8(mock.output(..)).unwrap(), alive)
+    }
+
+    fn params(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        match_path(&parse_pattern(pattern), path).map(|p| p.values)
+    }
+
+    fn pairs(items: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(items.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect())
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(params("/", "/"), pairs(&[]));
+        assert_eq!(params("/", "/x"), None);
+        assert_eq!(params("/users", "/users"), pairs(&[]));
+        assert_eq!(params("/users", "/users/"), None);
+        assert_eq!(params("/users", "/user"), None);
+        assert_eq!(params("/users/list", "/users"), None);
+    }
+
+    #[test]
+    fn named() {
+        assert_eq!(params("/users/:id", "/users/12"), pairs(&[("id", "12")]));
+        assert_eq!(params("/users/:id", "/users/"), None);
+        assert_eq!(params("/users/:id", "/users/12/x"), None);
+        assert_eq!(params("/:a/x/:b", "/1/x/2"),
+                   pairs(&[("a", "1"), ("b", "2")]));
+    }
+
+    #[test]
+    fn tail() {
+        assert_eq!(params("/static/*path", "/static/css/a.css"),
+                   pairs(&[("path", "css/a.css")]));
+        assert_eq!(params("/static/*path", "/static/"),
+                   pairs(&[("path", "")]));
+        assert_eq!(params("/static/*path", "/static"), None);
+        assert_eq!(params("/*", "/anything/at/all"),
+                   pairs(&[("", "anything/at/all")]));
+    }
+
+    #[test]
+    #[should_panic(expected="tail segment")]
+    fn tail_not_last() {
+        parse_pattern("/*path/x");
+    }
+
+    #[test]
+    fn allow() {
+        assert_eq!(allow_header(&[Method::Get, Method::Head, Method::Post]),
+                   "GET, HEAD, POST");
+    }
+
+    #[test]
+    fn route() {
+        assert_eq!(request("GET /users/17 HTTP/1.1\r\n\r\n"),
+            ("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n17".into(),
+             true));
+    }
+
+    #[test]
+    fn not_found() {
+        assert_eq!(request("GET /groups/1 HTTP/1.1\r\n\r\n"),
+            ("HTTP/1.1 404 Not Found\r\n\
+              Content-Type: text/plain\r\n\
+              Content-Length: 14\r\n\r\n\
+              404 Not Found\n".into(), true));
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        assert_eq!(request("DELETE /users/1 HTTP/1.1\r\n\r\n"),
+            ("HTTP/1.1 405 Method Not Allowed\r\n\
+              Allow: GET, HEAD\r\n\
+              Content-Type: text/plain\r\n\
+              Content-Length: 23\r\n\r\n\
+              405 Method Not Allowed\n".into(), true));
+    }
+
+    #[test]
+    fn head_served_by_get() {
+        assert_eq!(request("HEAD /users/17 HTTP/1.1\r\n\r\n"),
+            ("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n".into(),
+             true));
+    }
+
+    #[test]
+    fn error_page_rejects_body() {
+        assert_eq!(request("PUT /users/1 HTTP/1.1\r\n\
+                            Content-Length: 1000000\r\n\r\n"),
+            ("HTTP/1.1 405 Method Not Allowed\r\n\
+              Allow: GET, HEAD\r\n\
+              Content-Type: text/plain\r\n\
+              Content-Length: 23\r\n\
+              Connection: close\r\n\r\n\
+              405 Method Not Allowed\n".into(), false));
+    }
+}
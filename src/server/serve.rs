@@ -0,0 +1,241 @@
+use std::io;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{Future, Stream, Poll, Async};
+use futures::task::{self, Task};
+use tokio_core::net::{TcpListener, TcpStream, Incoming};
+use tokio_core::reactor::{Handle, Timeout};
+
+use super::{Config, Dispatcher, Proto, Shutdown};
+use super::error::log_level;
+
+
+struct Connections {
+    active: Cell<usize>,
+    task: RefCell<Option<Task>>,
+}
+
+/// Decrements number of active connections when connection is closed
+struct Guard(Rc<Connections>);
+
+/// A future returned by `serve()`
+///
+/// Resolves only if listening socket is closed or graceful shutdown is
+/// triggered. Errors of connections and most accept errors are logged and
+/// don't stop the server.
+pub struct Serve<F> {
+    incoming: Incoming,
+    config: Arc<Config>,
+    factory: F,
+    handle: Handle,
+    connections: Rc<Connections>,
+    delay: Option<Timeout>,
+    shutdown: Option<Shutdown>,
+}
+
+/// Accept connections on the listener and serve each of them using `Proto`
+///
+/// Dispatcher for every connection is created by `factory` which receives
+/// peer address. Every connection is spawned on the `handle`.
+///
+/// At most `config.max_connections` are served simultaneously. When an
+/// accept error occurs (usually because of EMFILE), accepting is paused
+/// for `config.accept_error_delay`.
+pub fn serve<F, D>(listener: TcpListener, config: &Arc<Config>, factory: F,
+    handle: &Handle)
+    -> Serve<F>
+    where F: FnMut(SocketAddr) -> D,
+          D: Dispatcher<TcpStream> + 'static,
+{
+    Serve {
+        incoming: listener.incoming(),
+        config: config.clone(),
+        factory: factory,
+        handle: handle.clone(),
+        connections: Rc::new(Connections {
+            active: Cell::new(0),
+            task: RefCell::new(None),
+        }),
+        delay: None,
+        shutdown: None,
+    }
+}
+
+impl<F> Serve<F> {
+    /// Shut down gracefully when the signal is triggered
+    ///
+    /// Every accepted connection gets a clone of the signal (see
+    /// `Proto::with_shutdown`). When it's triggered, no more connections
+    /// are accepted and this future resolves, while connections finish
+    /// requests in progress.
+    pub fn shutdown(&mut self, shutdown: &Shutdown) -> &mut Self {
+        self.shutdown = Some(shutdown.clone());
+        self
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let active = self.0.active.get();
+        self.0.active.set(active - 1);
+        if let Some(task) = self.0.task.borrow_mut().take() {
+            task.unpark();
+        }
+    }
+}
+
+impl<F, D> Future for Serve<F>
+    where F: FnMut(SocketAddr) -> D,
+          D: Dispatcher<TcpStream> + 'static,
+{
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if let Some(ref mut shutdown) = self.shutdown {
+                if matches!(shutdown.poll(), Ok(Async::Ready(()))) {
+                    return Ok(Async::Ready(()));
+                }
+            }
+            if let Some(mut delay) = self.delay.take() {
+                if let Async::NotReady = delay.poll()? {
+                    self.delay = Some(delay);
+                    return Ok(Async::NotReady);
+                }
+            }
+            let active = self.connections.active.get();
+            if active >= self.config.max_connections {
+                // Woken up by a `Guard` when some connection is closed
+                *self.connections.task.borrow_mut() = Some(task::park());
+                return Ok(Async::NotReady);
+            }
+            match self.incoming.poll() {
+                Ok(Async::Ready(Some((socket, addr)))) => {
+                    self.connections.active.set(active + 1);
+                    let guard = Guard(self.connections.clone());
+                    let dispatcher = (self.factory)(addr);
+                    let proto = match self.shutdown {
+                        Some(ref shutdown) => Proto::with_shutdown(socket,
+                            &self.config, dispatcher, &self.handle,
+                            shutdown.clone()),
+                        None => Proto::new(socket, &self.config,
+                            dispatcher, &self.handle),
+                    };
+                    self.handle.spawn(proto.then(move |result| {
+                        drop(guard);
+                        if let Err(e) = result {
+                            log!(log_level(&e),
+                                 "Connection error from {}: {}", addr, e);
+                        }
+                        Ok(())
+                    }));
+                }
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted
+                    || e.kind() == io::ErrorKind::ConnectionReset
+                    || e.kind() == io::ErrorKind::Interrupted
+                => {
+                    // Client has gone before we accepted connection
+                    debug!("Accept error: {}", e);
+                }
+                Err(e) => {
+                    warn!("Accept error: {}. Pausing for {:?}",
+                          e, self.config.accept_error_delay);
+                    self.delay = Some(Timeout::new(
+                        self.config.accept_error_delay, &self.handle)?);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net;
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use futures::Future;
+    use futures::future::FutureResult;
+    use tokio_core::net::{TcpListener, TcpStream};
+    use tokio_core::reactor::{Core, Timeout};
+
+    use server::{Config, Dispatcher, Codec, Head, Error, EncoderDone};
+    use server::Shutdown;
+    use super::serve;
+
+    struct Idle;
+
+    impl Dispatcher<TcpStream> for Idle {
+        type Codec = Box<Codec<TcpStream,
+            ResponseFuture=FutureResult<EncoderDone<TcpStream>, Error>>>;
+        fn headers_received(&mut self, _headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            unreachable!();
+        }
+    }
+
+    fn run_for(lp: &mut Core, ms: u64) {
+        let timeout = Timeout::new(Duration::from_millis(ms), &lp.handle());
+        lp.run(timeout.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn max_connections() {
+        let mut lp = Core::new().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &lp.handle()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Rc::new(Cell::new(0));
+        let counter = accepted.clone();
+        let cfg = Config::new().max_connections(1).done();
+        lp.handle().spawn(serve(listener, &cfg, move |_| {
+                counter.set(counter.get() + 1);
+                Idle
+            }, &lp.handle())
+            .map_err(|e| panic!("accept error: {}", e)));
+
+        let first = net::TcpStream::connect(addr).unwrap();
+        let _second = net::TcpStream::connect(addr).unwrap();
+        run_for(&mut lp, 50);
+        assert_eq!(accepted.get(), 1);
+        // closing connection wakes up the listener
+        drop(first);
+        run_for(&mut lp, 50);
+        assert_eq!(accepted.get(), 2);
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut lp = Core::new().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &lp.handle()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let done = Rc::new(Cell::new(false));
+        let flag = done.clone();
+        let mut server = serve(listener, &Config::new().done(),
+            |_| Idle, &lp.handle());
+        server.shutdown(&shutdown);
+        lp.handle().spawn(server
+            .map(move |()| flag.set(true))
+            .map_err(|e| panic!("accept error: {}", e)));
+
+        let mut conn = net::TcpStream::connect(addr).unwrap();
+        run_for(&mut lp, 50);
+        assert!(!done.get());
+        shutdown.trigger();
+        run_for(&mut lp, 50);
+        assert!(done.get());
+        // idle connection is closed
+        conn.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(conn.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use futures::{Future, Poll, Async};
+use futures::task::{self, Task};
+
+
+struct Inner {
+    triggered: AtomicBool,
+    next_id: AtomicUsize,
+    tasks: Mutex<HashMap<usize, Task>>,
+}
+
+/// A signal for graceful shutdown of server connections
+///
+/// Clone it and pass to `Proto::with_shutdown` for every connection, or
+/// to `Serve::shutdown` which does that for you. When `trigger()` is
+/// called on any of the clones, connections stop reading new requests,
+/// finish ones that are already in progress and close.
+///
+/// It's also a future that resolves when shutdown is triggered, so it may
+/// be used to stop accepting connections too.
+pub struct Shutdown {
+    id: usize,
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    /// Create a new, not yet triggered, shutdown signal
+    pub fn new() -> Shutdown {
+        Shutdown {
+            id: 0,
+            inner: Arc::new(Inner {
+                triggered: AtomicBool::new(false),
+                next_id: AtomicUsize::new(1),
+                tasks: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+    /// Trigger shutdown of all connections sharing this signal
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+        let mut tasks = self.inner.tasks.lock().expect("shutdown lock");
+        for (_, task) in tasks.drain() {
+            task.unpark();
+        }
+    }
+    /// Returns `true` if shutdown is already triggered
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+}
+
+impl Clone for Shutdown {
+    fn clone(&self) -> Shutdown {
+        Shutdown {
+            id: self.inner.next_id.fetch_add(1, Ordering::SeqCst),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        if let Ok(mut tasks) = self.inner.tasks.lock() {
+            tasks.remove(&self.id);
+        }
+    }
+}
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.is_triggered() {
+            return Ok(Async::Ready(()));
+        }
+        self.inner.tasks.lock().expect("shutdown lock")
+            .insert(self.id, task::park());
+        // Check again, in case trigger() was called before task is stored
+        if self.is_triggered() {
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("triggered", &self.is_triggered())
+            .finish()
+    }
+}
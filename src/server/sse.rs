@@ -0,0 +1,357 @@
+//! Server-Sent Events (`text/event-stream`) responses
+//!
+//! Use `EventStream` in `Codec::start_response` to turn a stream of
+//! `Event` values into a chunked response. Every event is sent as a single
+//! chunk, and a comment line is sent as a keep-alive when there were no
+//! events for a while.
+//!
+//! Note: the whole response is still limited by
+//! `Config::output_body_whole_timeout` (one hour by default). Browsers
+//! reconnect automatically, so use `last_event_id` to resume the stream.
+//!
+use std::io;
+use std::mem::replace;
+use std::time::{Duration, Instant};
+use std::ascii::AsciiExt;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_core::io::Io;
+use tokio_core::reactor::{Handle, Timeout};
+
+use enums::Status;
+use super::{Error, Encoder, EncoderDone, Head, WaitFlush};
+use super::error::ErrorEnum;
+
+
+/// Default interval between keep-alive comments
+const KEEP_ALIVE: u64 = 15;
+
+/// Default number of bytes in the output buffer, when we stop reading
+/// the event stream
+const WATERMARK: usize = 65536;
+
+
+/// A single event sent to the client
+///
+/// Only `data` is required, other fields are sent only when set. Newlines
+/// in `id` and `event` aren't allowed by the protocol, so they're stripped.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    /// Identifier of the event, it's sent back in `Last-Event-ID` header
+    /// when the client reconnects
+    pub id: Option<String>,
+    /// Event type, the `message` type is assumed by clients if not set
+    pub event: Option<String>,
+    /// Payload of the event, may contain multiple lines
+    pub data: String,
+    /// Tells the client how long to wait before reconnecting
+    pub retry: Option<Duration>,
+}
+
+/// A future that writes events from the stream to the response
+///
+/// Resolves when the stream is finished.
+pub struct EventStream<S: Io, E> {
+    state: State<S>,
+    events: E,
+    handle: Handle,
+    keep_alive: Duration,
+    last_write: Instant,
+    timeout: Timeout,
+    watermark: usize,
+}
+
+enum State<S: Io> {
+    Flush(WaitFlush<S>),
+    Read(Encoder<S>),
+    Void,
+}
+
+impl Event {
+    /// Create an event with just the data
+    pub fn new<T: Into<String>>(data: T) -> Event {
+        Event {
+            data: data.into(),
+            .. Event::default()
+        }
+    }
+}
+
+/// Returns the value of `Last-Event-ID` header, if any
+///
+/// This is sent by the client when it reconnects, so you can skip events
+/// that were delivered already.
+pub fn last_event_id<'x>(head: &'x Head) -> Option<&'x str> {
+    head.headers()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("Last-Event-ID"))
+        .and_then(|(_, value)| ::std::str::from_utf8(value).ok())
+        .map(|value| value.trim())
+}
+
+fn write_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend(name.as_bytes());
+    buf.extend(b": ");
+    buf.extend(value.bytes().filter(|&c| c != b'\r' && c != b'\n'));
+    buf.push(b'\n');
+}
+
+fn write_event(buf: &mut Vec<u8>, event: &Event) {
+    if let Some(ref id) = event.id {
+        write_field(buf, "id", id);
+    }
+    if let Some(ref name) = event.event {
+        write_field(buf, "event", name);
+    }
+    if let Some(retry) = event.retry {
+        let ms = retry.as_secs() * 1000 + (retry.subsec_nanos() / 1000000)
+            as u64;
+        write_field(buf, "retry", &ms.to_string());
+    }
+    for line in event.data.split('\n') {
+        let line = if line.ends_with('\r') {
+            &line[..line.len()-1]
+        } else {
+            line
+        };
+        write_field(buf, "data", line);
+    }
+    buf.push(b'\n');
+}
+
+impl<S: Io, E: Stream<Item=Event, Error=Error>> EventStream<S, E> {
+    /// Writes response headers and returns a future that sends events
+    ///
+    /// Response has `200 OK` status, `Content-Type: text/event-stream` and
+    /// chunked encoding. Keep-alive comments are sent every 15 seconds.
+    pub fn new(mut e: Encoder<S>, events: E, handle: &Handle)
+        -> EventStream<S, E>
+    {
+        e.status(Status::Ok);
+        e.add_header("Content-Type", "text/event-stream").unwrap();
+        e.add_header("Cache-Control", "no-cache").unwrap();
+        e.add_chunked().unwrap();
+        e.done_headers().unwrap();
+        let keep_alive = Duration::new(KEEP_ALIVE, 0);
+        EventStream {
+            state: State::Read(e),
+            events: events,
+            handle: handle.clone(),
+            keep_alive: keep_alive,
+            last_write: Instant::now(),
+            timeout: Timeout::new(keep_alive, handle)
+                .expect("can always add a timeout"),
+            watermark: WATERMARK,
+        }
+    }
+    /// Set interval of keep-alive comments
+    ///
+    /// Comment is sent only if there were no events for this period.
+    pub fn keep_alive(&mut self, interval: Duration) -> &mut Self {
+        self.keep_alive = interval;
+        self.timeout = Timeout::new(interval, &self.handle)
+            .expect("can always add a timeout");
+        self
+    }
+    /// Set the size of output buffer when we stop reading the stream
+    ///
+    /// Default is 64KiB.
+    pub fn watermark(&mut self, bytes: usize) -> &mut Self {
+        self.watermark = bytes;
+        self
+    }
+    fn poll_keep_alive(&mut self) -> Result<bool, io::Error> {
+        loop {
+            match self.timeout.poll()? {
+                Async::Ready(()) => {}
+                Async::NotReady => return Ok(false),
+            }
+            let deadline = self.last_write + self.keep_alive;
+            let now = Instant::now();
+            if deadline <= now {
+                self.last_write = now;
+                self.timeout = Timeout::new(self.keep_alive, &self.handle)?;
+                return Ok(true);
+            }
+            self.timeout = Timeout::new(deadline - now, &self.handle)?;
+        }
+    }
+}
+
+impl<S: Io, E: Stream<Item=Event, Error=Error>> Future for EventStream<S, E> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        use self::State::*;
+        loop {
+            let next = match replace(&mut self.state, Void) {
+                Flush(mut f) => match f.poll().map_err(ErrorEnum::Io)? {
+                    Async::Ready(e) => Read(e),
+                    Async::NotReady => {
+                        self.state = Flush(f);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Read(mut e) => match self.events.poll()? {
+                    Async::Ready(Some(event)) => {
+                        let mut buf = Vec::new();
+                        write_event(&mut buf, &event);
+                        e.write_body(&buf);
+                        self.last_write = Instant::now();
+                        Flush(e.wait_flush(self.watermark))
+                    }
+                    Async::Ready(None) => {
+                        return Ok(Async::Ready(e.done()));
+                    }
+                    Async::NotReady => {
+                        if self.poll_keep_alive().map_err(ErrorEnum::Io)? {
+                            e.write_body(b":\n\n");
+                            Flush(e.wait_flush(self.watermark))
+                        } else {
+                            self.state = Read(e);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                },
+                Void => unreachable!(),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::{Future, Async, Poll, Stream};
+    use futures::future::lazy;
+    use tk_bufstream::{IoBuf, Mock, MockData};
+    use tokio_core::io::Io;
+    use tokio_core::reactor::{Core, Timeout};
+
+    use enums::Version;
+    use server::{Encoder, EncoderDone, Error};
+    use server::encoder::{self, ResponseConfig, get_inner};
+    use super::{Event, EventStream, write_event};
+
+    /// Yields events from the queue, `None` in the queue ends the stream
+    struct Events(Rc<RefCell<VecDeque<Option<Event>>>>);
+
+    impl Stream for Events {
+        type Item = Event;
+        type Error = Error;
+        fn poll(&mut self) -> Poll<Option<Event>, Error> {
+            match self.0.borrow_mut().pop_front() {
+                Some(event) => Ok(Async::Ready(event)),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn encoder<S: Io>(io: S) -> Encoder<S> {
+        let (output, _) = IoBuf::new(io).split();
+        encoder::new(output, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http11,
+            expect_continue: false,
+            continue_sent: false,
+        }, &Default::default())
+    }
+
+    fn poll<S: Io>(lp: &mut Core, stream: &mut EventStream<S, Events>)
+        -> Option<EncoderDone<S>>
+    {
+        match lp.run(lazy(|| stream.poll())).unwrap() {
+            Async::Ready(done) => Some(done),
+            Async::NotReady => None,
+        }
+    }
+
+    fn event(e: &Event) -> String {
+        let mut buf = Vec::new();
+        write_event(&mut buf, e);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn data_only() {
+        assert_eq!(event(&Event::new("hello")), "data: hello\n\n");
+        assert_eq!(event(&Event::new("")), "data: \n\n");
+    }
+
+    #[test]
+    fn multiline() {
+        assert_eq!(event(&Event::new("a\nb\r\nc")),
+                   "data: a\ndata: b\ndata: c\n\n");
+        assert_eq!(event(&Event::new("a\n")), "data: a\ndata: \n\n");
+    }
+
+    #[test]
+    fn all_fields() {
+        let e = Event {
+            id: Some("17".into()),
+            event: Some("update\n".into()),
+            data: "{}".into(),
+            retry: Some(Duration::from_millis(2500)),
+        };
+        assert_eq!(event(&e),
+            "id: 17\nevent: update\nretry: 2500\ndata: {}\n\n");
+    }
+
+    #[test]
+    fn stream() {
+        let mut lp = Core::new().unwrap();
+        let mock = MockData::new();
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let mut stream = EventStream::new(encoder(mock.clone()),
+            Events(queue.clone()), &lp.handle());
+        queue.borrow_mut().push_back(Some(Event::new("hello")));
+        assert!(poll(&mut lp, &mut stream).is_none());
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/event-stream\r\n\
+             Cache-Control: no-cache\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             d\r\ndata: hello\n\n\r\n");
+        queue.borrow_mut().push_back(None);
+        let done = poll(&mut lp, &mut stream).expect("stream is done");
+        get_inner(done).flush().unwrap();
+        assert!(mock.output(..).ends_with(b"\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut lp = Core::new().unwrap();
+        let mock = MockData::new();
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let mut stream = EventStream::new(encoder(mock.clone()),
+            Events(queue.clone()), &lp.handle());
+        stream.keep_alive(Duration::from_millis(10));
+        assert!(poll(&mut lp, &mut stream).is_none());
+        // nothing to send yet, even headers are buffered
+        assert_eq!(mock.output(..), b"");
+        let pause = Timeout::new(Duration::from_millis(20), &lp.handle());
+        lp.run(pause.unwrap()).unwrap();
+        assert!(poll(&mut lp, &mut stream).is_none());
+        assert!(mock.output(..).ends_with(b"\r\n\r\n3\r\n:\n\n\r\n"));
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut lp = Core::new().unwrap();
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let mut stream = EventStream::new(encoder(Mock),
+            Events(queue.clone()), &lp.handle());
+        stream.watermark(0);
+        queue.borrow_mut().push_back(Some(Event::new("a")));
+        queue.borrow_mut().push_back(Some(Event::new("b")));
+        assert!(poll(&mut lp, &mut stream).is_none());
+        assert!(poll(&mut lp, &mut stream).is_none());
+        // next event isn't read until the first one is flushed
+        assert_eq!(queue.borrow().len(), 1);
+    }
+}
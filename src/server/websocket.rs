@@ -2,7 +2,7 @@ use std::ascii::AsciiExt;
 use std::str::{from_utf8};
 
 use super::{Head};
-use websocket::Accept;
+use websocket::{Accept, Config, Deflate};
 
 
 /// Contains all the imporant parts of a websocket handshake
@@ -17,6 +17,18 @@ pub struct WebsocketHandshake {
 }
 
 
+impl WebsocketHandshake {
+    /// Negotiate permessage-deflate extension
+    ///
+    /// Returns parameters for the first acceptable offer of the client, if
+    /// any. The returned value must be sent to the client in the
+    /// `Sec-WebSocket-Extensions` header (it implements `Display` for that)
+    /// and then passed to `Loop::deflate()` or `ServerCodec::deflate()`.
+    pub fn deflate(&self, cfg: &Config) -> Option<Deflate> {
+        Deflate::accept_offer(&self.extensions, cfg)
+    }
+}
+
 fn bytes_trim(mut x: &[u8]) -> &[u8] {
     while x.len() > 0 && matches!(x[0], b'\r' | b'\n' | b' ' | b'\t') {
         x = &x[1..];
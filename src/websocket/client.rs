@@ -2,6 +2,7 @@
 //!
 use std::ascii::AsciiExt;
 use std::fmt::Display;
+use std::str::from_utf8;
 
 use futures::{Future, Async};
 use httparse::{self, Header};
@@ -13,7 +14,8 @@ use base_serializer::{MessageState, HeaderError};
 use websocket::{Error};
 use websocket::error::ErrorEnum;
 use enums::{Version, Status};
-use websocket::{ClientCodec, Key};
+use websocket::{ClientCodec, Key, Accept, Config, Deflate};
+use websocket::deflate::{client_offer, check_response};
 
 
 
@@ -29,12 +31,15 @@ const MAX_HEADERS: usize = 1024;
 pub struct Encoder<S: Io> {
     message: MessageState,
     buf: WriteBuf<S>,
+    deflate: Option<Deflate>,
 }
 
 /// This structure returned from `Encoder::done` and works as a continuation
 /// that should be returned from the future that writes request.
 pub struct EncoderDone<S: Io> {
     buf: WriteBuf<S>,
+    accept: Accept,
+    deflate: Option<Deflate>,
 }
 
 /// Authorizer sends all the necessary headers and checks response headers
@@ -56,10 +61,13 @@ pub trait Authorizer<S: Io> {
     /// A handler of response headers
     ///
     /// It's called when websocket has been sucessfully connected or when
-    /// server returned error, check that response code equals 101 to make
-    /// sure response is established.
+    /// server returned error. For the latter the handshake fails with
+    /// an error anyway, but handler may inspect the response to return
+    /// a more meaningful error.
     ///
-    /// Anyway, handler may be skipped in case of invalid response headers.
+    /// Handler is skipped if server returned `101 Switching Protocols` but
+    /// response headers are not valid for a websocket (i.e. no `Upgrade`
+    /// header or wrong `Sec-WebSocket-Accept`).
     fn headers_received(&mut self, headers: &Head)
         -> Result<Self::Result, Error>;
 }
@@ -75,6 +83,7 @@ pub struct Head<'a> {
     code: u16,
     reason: &'a str,
     headers: &'a [Header<'a>],
+    deflate: Option<Deflate>,
 }
 
 /// A future that resolves to framed streams when websocket handshake is done
@@ -82,6 +91,8 @@ pub struct HandshakeProto<S, A> {
     input: Option<ReadBuf<S>>,
     output: Option<WriteBuf<S>>,
     authorizer: A,
+    accept: Accept,
+    deflate_offer: Option<Deflate>,
 }
 
 /// Default handshake handler, if you just want to get websocket connected
@@ -185,6 +196,23 @@ impl<S: Io> Encoder<S> {
         check_header(name);
         self.message.format_header(&mut self.buf.out_buf, name, value)
     }
+    /// Offer permessage-deflate extension with parameters from the config
+    ///
+    /// If server accepts the offer, negotiated parameters are available
+    /// as `Head::deflate()` and framed streams returned by `HandshakeProto`
+    /// have compression enabled. `Loop::deflate()` must be called
+    /// explicitly though.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called twice or in the wrong state.
+    pub fn offer_deflate(&mut self, cfg: &Config) {
+        assert!(self.deflate.is_none());
+        let offer = client_offer(cfg);
+        self.message.format_header(&mut self.buf.out_buf,
+            "Sec-WebSocket-Extensions", &offer).unwrap();
+        self.deflate = Some(offer);
+    }
     /// Finish writing headers and return `EncoderDone` which can be moved to
     ///
     /// # Panics
@@ -195,15 +223,19 @@ impl<S: Io> Encoder<S> {
             "Connection", b"upgrade").unwrap();
         self.message.add_header(&mut self.buf.out_buf,
             "Upgrade", b"websocket").unwrap();
-        // TODO(tailhook) generate real random key
+        let key = Key::new();
         self.message.format_header(&mut self.buf.out_buf,
-            "Sec-WebSocket-Key", Key::new()).unwrap();
+            "Sec-WebSocket-Key", &key).unwrap();
         self.message.add_header(&mut self.buf.out_buf,
             "Sec-WebSocket-Version", b"13").unwrap();
         self.message.done_headers(&mut self.buf.out_buf)
             .map(|ignore_body| assert!(ignore_body)).unwrap();
         self.message.done(&mut self.buf.out_buf);
-        EncoderDone { buf: self.buf }
+        EncoderDone {
+            buf: self.buf,
+            accept: Accept::from_key_bytes(key.to_string().as_bytes()),
+            deflate: self.deflate,
+        }
     }
 }
 
@@ -211,28 +243,75 @@ fn encoder<S: Io>(io: WriteBuf<S>) -> Encoder<S> {
     Encoder {
         message: MessageState::RequestStart,
         buf: io,
+        deflate: None,
+    }
+}
+
+/// Checks that response to the upgrade request is valid
+///
+/// Returns parameters of permessage-deflate if server accepted it
+fn check_upgrade(headers: &[Header], accept: &Accept,
+    deflate_offer: Option<&Deflate>)
+    -> Result<Option<Deflate>, ErrorEnum>
+{
+    let expected_accept = accept.to_string();
+    let mut upgrade = false;
+    let mut connection = false;
+    let mut has_accept = false;
+    let mut extensions = Vec::new();
+    for h in headers {
+        let value = from_utf8(h.value).map(|x| x.trim());
+        if h.name.eq_ignore_ascii_case("Upgrade") {
+            upgrade = value.map(|x| x.eq_ignore_ascii_case("websocket"))
+                .unwrap_or(false);
+        } else if h.name.eq_ignore_ascii_case("Connection") {
+            connection = value.map(|x| x.split(',')
+                    .any(|tok| tok.trim().eq_ignore_ascii_case("upgrade")))
+                .unwrap_or(false);
+        } else if h.name.eq_ignore_ascii_case("Sec-WebSocket-Accept") {
+            if has_accept || value != Ok(&expected_accept[..]) {
+                return Err(ErrorEnum::InvalidAccept);
+            }
+            has_accept = true;
+        } else if h.name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+            let value = value.map_err(|_| ErrorEnum::BadExtension)?;
+            extensions.extend(value.split(',')
+                .map(|x| x.trim())
+                .filter(|x| x.len() > 0));
+        }
+    }
+    if !upgrade || !connection {
+        return Err(ErrorEnum::InvalidUpgrade);
     }
+    if !has_accept {
+        return Err(ErrorEnum::InvalidAccept);
+    }
+    check_response(extensions.into_iter(), deflate_offer)
 }
 
 impl<S: Io, A: Authorizer<S>> HandshakeProto<S, A> {
     /// Create an instance of future from already connected socket
     pub fn new(transport: S, mut authorizer: A) -> HandshakeProto<S, A> {
         let (tx, rx) = IoBuf::new(transport).split();
-        let out = authorizer.write_headers(encoder(tx)).buf;
+        let done = authorizer.write_headers(encoder(tx));
         HandshakeProto {
             authorizer: authorizer,
             input: Some(rx),
-            output: Some(out),
+            output: Some(done.buf),
+            accept: done.accept,
+            deflate_offer: done.deflate,
         }
     }
-    fn parse_headers(&mut self) -> Result<Option<A::Result>, Error> {
+    fn parse_headers(&mut self)
+        -> Result<Option<(A::Result, Option<Deflate>)>, Error>
+    {
         let ref mut buf = self.input.as_mut()
             .expect("buffer still exists")
             .in_buf;
         let (res, bytes) = {
             let mut vec;
             let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
-            let (code, reason, headers, bytes) = {
+            let (version, code, reason, headers, bytes) = {
                 let mut raw = httparse::Response::new(&mut headers);
                 let mut result = raw.parse(&buf[..]);
                 if matches!(result, Err(httparse::Error::TooManyHeaders)) {
@@ -242,25 +321,40 @@ impl<S: Io, A: Authorizer<S>> HandshakeProto<S, A> {
                 }
                 match result.map_err(ErrorEnum::HeaderError)? {
                     httparse::Status::Complete(bytes) => {
-                        let ver = raw.version.unwrap();
-                        if ver != 1 {
-                            //return Error::VersionTooOld;
-                            unimplemented!();
-                        }
+                        let ver = if raw.version.unwrap() == 1 {
+                            Version::Http11
+                        } else {
+                            Version::Http10
+                        };
                         let code = raw.code.unwrap();
-                        (code, raw.reason.unwrap(), raw.headers, bytes)
+                        (ver, code, raw.reason.unwrap(), raw.headers, bytes)
                     }
                     _ => return Ok(None),
                 }
             };
+            // HTTP/1.0 has no upgrade mechanism, but we still pass the
+            // response to the authorizer so it can find out what's wrong
+            let deflate = if code == 101 && version == Version::Http11 {
+                check_upgrade(headers, &self.accept,
+                              self.deflate_offer.as_ref())?
+            } else {
+                None
+            };
             let head = Head {
-                version: Version::Http11,
+                version: version,
                 code: code,
                 reason: reason,
                 headers: headers,
+                deflate: deflate,
             };
             let data = self.authorizer.headers_received(&head)?;
-            (data, bytes)
+            if version != Version::Http11 {
+                return Err(ErrorEnum::VersionTooOld.into());
+            }
+            if code != 101 {
+                return Err(ErrorEnum::InvalidStatus(code).into());
+            }
+            ((data, head.deflate), bytes)
         };
         buf.consume(bytes);
         return Ok(Some(res));
@@ -282,13 +376,18 @@ impl<S: Io, A> Future for HandshakeProto<S, A>
             return Err(ErrorEnum::PrematureResponseHeaders.into());
         }
         match self.parse_headers()? {
-            Some(x) => {
+            Some((x, deflate)) => {
+                let (icodec, ocodec) = match deflate {
+                    Some(ref params) => (ClientCodec::new().deflate(params),
+                                         ClientCodec::new().deflate(params)),
+                    None => (ClientCodec::new(), ClientCodec::new()),
+                };
                 let inp = self.input.take()
                     .expect("input still here")
-                    .framed(ClientCodec);
+                    .framed(icodec);
                 let out = self.output.take()
                     .expect("input still here")
-                    .framed(ClientCodec);
+                    .framed(ocodec);
                 Ok(Async::Ready((out, inp, x)))
             }
             None => Ok(Async::NotReady),
@@ -297,6 +396,14 @@ impl<S: Io, A> Future for HandshakeProto<S, A>
 }
 
 impl<'a> Head<'a> {
+    /// HTTP version of the response
+    ///
+    /// Note: websocket can only be established over HTTP/1.1, for HTTP/1.0
+    /// response handshake fails anyway, after the response is passed to
+    /// the `Authorizer`.
+    pub fn version(&self) -> Version {
+        self.version
+    }
     /// Returns status if it is one of the supported statuses otherwise None
     ///
     /// Note: this method does not consider "reason" string at all just
@@ -320,4 +427,94 @@ impl<'a> Head<'a> {
     pub fn all_headers(&self) -> &'a [Header<'a>] {
         self.headers
     }
+    /// Parameters of permessage-deflate extension if server accepted it
+    ///
+    /// This is only available if `Encoder::offer_deflate()` was called,
+    /// pass the value to the `Loop::deflate()` to enable compression.
+    pub fn deflate(&self) -> Option<&Deflate> {
+        self.deflate.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use futures::{Future, Async};
+    use tk_bufstream::MockData;
+
+    use websocket::Accept;
+    use super::{HandshakeProto, SimpleAuthorizer};
+
+    fn handshake() -> (MockData, HandshakeProto<MockData, SimpleAuthorizer>)
+    {
+        let mock = MockData::new();
+        let mut proto = HandshakeProto::new(mock.clone(),
+            SimpleAuthorizer::new("example.com", "/"));
+        assert!(matches!(proto.poll(), Ok(Async::NotReady)));
+        (mock, proto)
+    }
+
+    fn accept(mock: &MockData) -> Accept {
+        let output = mock.output(..);
+        let key = from_utf8(&output).unwrap().lines()
+            .find(|x| x.starts_with("Sec-WebSocket-Key:")).unwrap()
+            ["Sec-WebSocket-Key:".len()..].trim().to_string();
+        Accept::from_key_bytes(key.as_bytes())
+    }
+
+    fn error(proto: &mut HandshakeProto<MockData, SimpleAuthorizer>)
+        -> String
+    {
+        match proto.poll() {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("handshake should fail"),
+        }
+    }
+
+    #[test]
+    fn valid_handshake() {
+        let (mock, mut proto) = handshake();
+        mock.add_input(&format!("HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n\r\n", accept(&mock))[..]);
+        assert!(matches!(proto.poll(), Ok(Async::Ready(_))));
+    }
+
+    #[test]
+    fn invalid_accept() {
+        let (mock, mut proto) = handshake();
+        mock.add_input("HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n");
+        assert_eq!(error(&mut proto), "invalid Sec-WebSocket-Accept header");
+    }
+
+    #[test]
+    fn no_upgrade() {
+        let (mock, mut proto) = handshake();
+        mock.add_input(&format!("HTTP/1.1 101 Switching Protocols\r\n\
+            Sec-WebSocket-Accept: {}\r\n\r\n", accept(&mock))[..]);
+        assert_eq!(error(&mut proto),
+                   "response has no websocket upgrade headers");
+    }
+
+    #[test]
+    fn http10_response() {
+        let (mock, mut proto) = handshake();
+        mock.add_input("HTTP/1.0 403 Forbidden\r\n\r\n");
+        assert_eq!(error(&mut proto),
+                   "websocket requires HTTP/1.1 or newer");
+    }
+
+    #[test]
+    fn not_switching_protocols() {
+        let (mock, mut proto) = handshake();
+        mock.add_input("HTTP/1.1 200 OK\r\n\
+            Content-Length: 0\r\n\r\n");
+        assert_eq!(error(&mut proto),
+                   "server didn't switch protocols, status: 200");
+    }
 }
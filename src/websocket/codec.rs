@@ -1,8 +1,9 @@
 use std::io;
 use tk_bufstream::{Buf, Encode, Decode};
 
-use websocket::{Packet};
-use websocket::zero_copy::{parse_frame, write_packet, write_close};
+use websocket::{Packet, Config, Deflate};
+use websocket::deflate::Compressor;
+use websocket::zero_copy::{Parser, write_packet, write_message, write_close};
 use websocket::error::Error;
 
 
@@ -12,33 +13,146 @@ const MAX_PACKET_SIZE: usize = 10 << 20;
 ///
 /// This codec is used out of the box in
 /// `BufferedDispatcher::new_with_websockets`
-pub struct ServerCodec;
+///
+/// Note: this used to be a unit struct. The codec keeps state of
+/// fragmented messages now, so use `ServerCodec::default()` (or
+/// `ServerCodec::new()`) where `ServerCodec` value was used before.
+pub struct ServerCodec {
+    parser: Parser,
+    compressor: Option<Compressor>,
+    max_packet_size: usize,
+    fragment_size: Option<usize>,
+}
 
 /// Websocket codec for use with tk-bufstream
 ///
 /// This codec is used out of the box in `HandshakeProto`
-pub struct ClientCodec;
+///
+/// Note: this used to be a unit struct. The codec keeps state of
+/// fragmented messages now, so use `ClientCodec::default()` (or
+/// `ClientCodec::new()`) where `ClientCodec` value was used before.
+pub struct ClientCodec {
+    parser: Parser,
+    compressor: Option<Compressor>,
+    max_packet_size: usize,
+    fragment_size: Option<usize>,
+}
+
+fn write_data(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool,
+    fragment_size: Option<usize>, compressor: Option<&mut Compressor>)
+{
+    match compressor {
+        Some(compressor) => {
+            let data = compressor.compress(data);
+            write_message(buf, opcode, &data, mask, fragment_size, true);
+        }
+        None => write_message(buf, opcode, data, mask, fragment_size, false),
+    }
+}
+
+/// Encodes a packet, splitting text and binary messages into fragments
+/// and compressing them if permessage-deflate is enabled
+pub fn encode(data: Packet, buf: &mut Buf, mask: bool,
+    fragment_size: Option<usize>, compressor: Option<&mut Compressor>)
+{
+    use super::Packet::*;
+    match data {
+        Ping(data) => write_packet(buf, 0x9, &data, mask),
+        Pong(data) => write_packet(buf, 0xA, &data, mask),
+        Text(data) => {
+            write_data(buf, 0x1, data.as_bytes(), mask,
+                       fragment_size, compressor)
+        }
+        Binary(data) => {
+            write_data(buf, 0x2, &data, mask, fragment_size, compressor)
+        }
+        // TODO(tailhook) should we also change state somehow?
+        Close(c, t) => write_close(buf, c, &t, mask),
+    }
+}
+
+impl ServerCodec {
+    /// Create a codec with default limits and no fragmentation of
+    /// outgoing messages
+    pub fn new() -> ServerCodec {
+        ServerCodec {
+            parser: Parser::new(),
+            compressor: None,
+            max_packet_size: MAX_PACKET_SIZE,
+            fragment_size: None,
+        }
+    }
+    /// Create a codec that uses `max_packet_size` and `fragment_size`
+    /// from the config
+    pub fn with_config(cfg: &Config) -> ServerCodec {
+        ServerCodec {
+            parser: Parser::new(),
+            compressor: None,
+            max_packet_size: cfg.max_packet_size,
+            fragment_size: cfg.fragment_size,
+        }
+    }
+    /// Enable permessage-deflate with parameters negotiated in handshake
+    pub fn deflate(mut self, params: &Deflate) -> ServerCodec {
+        self.parser.set_deflate(params, true);
+        self.compressor = Some(Compressor::new(params, true));
+        self
+    }
+}
 
+impl ClientCodec {
+    /// Create a codec with default limits and no fragmentation of
+    /// outgoing messages
+    pub fn new() -> ClientCodec {
+        ClientCodec {
+            parser: Parser::new(),
+            compressor: None,
+            max_packet_size: MAX_PACKET_SIZE,
+            fragment_size: None,
+        }
+    }
+    /// Create a codec that uses `max_packet_size` and `fragment_size`
+    /// from the config
+    pub fn with_config(cfg: &Config) -> ClientCodec {
+        ClientCodec {
+            parser: Parser::new(),
+            compressor: None,
+            max_packet_size: cfg.max_packet_size,
+            fragment_size: cfg.fragment_size,
+        }
+    }
+    /// Enable permessage-deflate with parameters negotiated in handshake
+    pub fn deflate(mut self, params: &Deflate) -> ClientCodec {
+        self.parser.set_deflate(params, false);
+        self.compressor = Some(Compressor::new(params, false));
+        self
+    }
+}
+
+impl Default for ServerCodec {
+    fn default() -> ServerCodec {
+        ServerCodec::new()
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> ClientCodec {
+        ClientCodec::new()
+    }
+}
 
 impl Encode for ServerCodec {
     type Item = Packet;
     fn encode(&mut self, data: Packet, buf: &mut Buf) {
-        use super::Packet::*;
-        match data {
-            Ping(data) => write_packet(buf, 0x9, &data, false),
-            Pong(data) => write_packet(buf, 0xA, &data, false),
-            Text(data) => write_packet(buf, 0x1, data.as_bytes(), false),
-            Binary(data) => write_packet(buf, 0x2, &data, false),
-            // TODO(tailhook) should we also change state somehow?
-            Close(c, t) => write_close(buf, c, &t, false),
-        }
+        encode(data, buf, false, self.fragment_size,
+               self.compressor.as_mut())
     }
 }
 
 impl Decode for ServerCodec {
     type Item = Packet;
     fn decode(&mut self, buf: &mut Buf) -> Result<Option<Packet>, io::Error> {
-        let parse_result = parse_frame(buf, MAX_PACKET_SIZE, true)
+        let parse_result = self.parser.parse(buf, self.max_packet_size, true)
             // TODO(tailhook) fix me when error type in bufstream
             // is associated type
             .map_err(|e| io::Error::new(io::ErrorKind::Other, Error::from(e)))?
@@ -55,22 +169,15 @@ impl Decode for ServerCodec {
 impl Encode for ClientCodec {
     type Item = Packet;
     fn encode(&mut self, data: Packet, buf: &mut Buf) {
-        use super::Packet::*;
-        match data {
-            Ping(data) => write_packet(buf, 0x9, &data, true),
-            Pong(data) => write_packet(buf, 0xA, &data, true),
-            Text(data) => write_packet(buf, 0x1, data.as_bytes(), true),
-            Binary(data) => write_packet(buf, 0x2, &data, true),
-            // TODO(tailhook) should we also change state somehow?
-            Close(c, t) => write_close(buf, c, &t, true),
-        }
+        encode(data, buf, true, self.fragment_size,
+               self.compressor.as_mut())
     }
 }
 
 impl Decode for ClientCodec {
     type Item = Packet;
     fn decode(&mut self, buf: &mut Buf) -> Result<Option<Packet>, io::Error> {
-        let parse_result = parse_frame(buf, MAX_PACKET_SIZE, false)
+        let parse_result = self.parser.parse(buf, self.max_packet_size, false)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
             .map(|(p, b)| (p.into(), b));
         if let Some((p, b)) = parse_result {
@@ -10,6 +10,10 @@ impl Config {
             ping_interval: Duration::new(10, 0),
             inactivity_timeout: Duration::new(30, 0),
             max_packet_size: 10 << 20,
+            fragment_size: None,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            deflate_max_window_bits: 15,
         }
     }
     /// Set ping interval
@@ -55,12 +59,64 @@ impl Config {
     /// Maximum packet size
     ///
     /// If some frame declares size larger than this, we immediately abort
-    /// the connection
+    /// the connection. For fragmented messages the limit is applied to the
+    /// total size of the message.
     pub fn max_packet_size(&mut self, size: usize) -> &mut Self {
         self.max_packet_size = size;
         self
     }
 
+    /// Split outgoing messages into fragments of at most this size
+    ///
+    /// By default messages are never fragmented. Fragmenting large messages
+    /// allows peer to start processing (and us to interleave pings with)
+    /// a message before it's fully sent.
+    pub fn fragment_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0);
+        self.fragment_size = Some(size);
+        self
+    }
+
+    /// Require server to reset compression context after each message
+    ///
+    /// This is a parameter of permessage-deflate extension. On the server
+    /// side we don't keep compression context between messages and announce
+    /// that to the client, on the client side we request that in the offer.
+    ///
+    /// This saves memory of the client at the cost of compression ratio.
+    /// Default is `false`.
+    pub fn server_no_context_takeover(&mut self, value: bool) -> &mut Self {
+        self.server_no_context_takeover = value;
+        self
+    }
+
+    /// Require client to reset compression context after each message
+    ///
+    /// This is a parameter of permessage-deflate extension. On the client
+    /// side we don't keep compression context between messages, on the
+    /// server side we require that from the client.
+    ///
+    /// Default is `false`.
+    pub fn client_no_context_takeover(&mut self, value: bool) -> &mut Self {
+        self.client_no_context_takeover = value;
+        self
+    }
+
+    /// Maximum LZ77 window size (in bits) we allow peer to use for
+    /// permessage-deflate compression
+    ///
+    /// It's sent as `client_max_window_bits` by the server (if client
+    /// supports the parameter) and as `server_max_window_bits` by the
+    /// client. Must be in range `8..16`, default is 15.
+    ///
+    /// Note: our own compressor always uses 15 bits window, so offers
+    /// that limit our window are declined.
+    pub fn deflate_max_window_bits(&mut self, bits: u8) -> &mut Self {
+        assert!(bits >= 8 && bits <= 15);
+        self.deflate_max_window_bits = bits;
+        self
+    }
+
     /// Create a Arc'd config clone to pass to the constructor
     ///
     /// This is just a convenience method.
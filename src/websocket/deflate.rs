@@ -0,0 +1,381 @@
+use std::ascii::AsciiExt;
+use std::cmp::max;
+use std::fmt;
+
+use flate2::{Compress, Decompress, Compression, Flush, Status};
+
+use websocket::Config;
+use websocket::error::ErrorEnum;
+
+
+const NAME: &'static str = "permessage-deflate";
+/// Trailer that is stripped from every compressed message (RFC 7692 7.2.1)
+const TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Parameters of the permessage-deflate extension (RFC 7692)
+///
+/// On the server side it's returned by `WebsocketHandshake::deflate()`, and
+/// should be sent back to the client formatted into
+/// `Sec-WebSocket-Extensions` response header. On the client side it's
+/// available as `client::Head::deflate()` if server accepted the offer.
+///
+/// Then pass it to `Loop::deflate()` (or to `deflate()` of the codec) to
+/// enable compression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deflate {
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    server_max_window_bits: Option<u8>,
+    client_max_window_bits: Option<u8>,
+}
+
+/// Compresses outgoing messages
+pub struct Compressor {
+    inner: Compress,
+    reset: bool,
+}
+
+/// Decompresses incoming messages
+pub struct Decompressor {
+    inner: Decompress,
+    reset: bool,
+}
+
+fn window_bits(value: &str) -> Result<u8, ()> {
+    match value.parse() {
+        Ok(x @ 8...15) => Ok(x),
+        _ => Err(()),
+    }
+}
+
+/// Parses single extension from `Sec-WebSocket-Extensions`
+///
+/// Returns `Ok(None)` if this is some other extension
+fn parse(extension: &str) -> Result<Option<Deflate>, ()> {
+    let mut parts = extension.split(';').map(|x| x.trim());
+    if !parts.next().map(|x| x.eq_ignore_ascii_case(NAME)).unwrap_or(false) {
+        return Ok(None);
+    }
+    let mut result = Deflate {
+        server_no_context_takeover: false,
+        client_no_context_takeover: false,
+        server_max_window_bits: None,
+        client_max_window_bits: None,
+    };
+    let mut seen = Vec::new();
+    for param in parts.filter(|x| x.len() > 0) {
+        let mut pair = param.splitn(2, '=');
+        let name = pair.next().unwrap().trim();
+        let value = pair.next().map(|x| x.trim().trim_matches('"'));
+        if seen.contains(&name) {
+            return Err(());
+        }
+        seen.push(name);
+        match (name, value) {
+            ("server_no_context_takeover", None) => {
+                result.server_no_context_takeover = true;
+            }
+            ("client_no_context_takeover", None) => {
+                result.client_no_context_takeover = true;
+            }
+            ("server_max_window_bits", Some(value)) => {
+                result.server_max_window_bits = Some(window_bits(value)?);
+            }
+            // Without a value it just means client supports the parameter
+            ("client_max_window_bits", None) => {
+                result.client_max_window_bits = Some(15);
+            }
+            ("client_max_window_bits", Some(value)) => {
+                result.client_max_window_bits = Some(window_bits(value)?);
+            }
+            _ => return Err(()),
+        }
+    }
+    Ok(Some(result))
+}
+
+/// Makes an offer that client sends to the server
+pub fn client_offer(cfg: &Config) -> Deflate {
+    Deflate {
+        server_no_context_takeover: cfg.server_no_context_takeover,
+        client_no_context_takeover: cfg.client_no_context_takeover,
+        server_max_window_bits: if cfg.deflate_max_window_bits < 15 {
+            Some(cfg.deflate_max_window_bits)
+        } else {
+            None
+        },
+        client_max_window_bits: None,
+    }
+}
+
+/// Validates server response against the offer
+///
+/// Extensions other than permessage-deflate are ignored, they are checked
+/// by `Authorizer` if it has offered any.
+pub fn check_response<'a, I>(extensions: I, offer: Option<&Deflate>)
+    -> Result<Option<Deflate>, ErrorEnum>
+    where I: Iterator<Item=&'a str>
+{
+    let mut result = None;
+    for ext in extensions {
+        let mut resp = match parse(ext) {
+            Ok(Some(resp)) => resp,
+            Ok(None) => continue,
+            Err(()) => return Err(ErrorEnum::BadExtension),
+        };
+        let offer = match offer {
+            Some(offer) if result.is_none() => offer,
+            _ => return Err(ErrorEnum::BadExtension),
+        };
+        if resp.client_max_window_bits.map(|x| x < 15).unwrap_or(false) {
+            return Err(ErrorEnum::BadExtension);
+        }
+        if offer.server_no_context_takeover &&
+            !resp.server_no_context_takeover
+        {
+            return Err(ErrorEnum::BadExtension);
+        }
+        match (offer.server_max_window_bits, resp.server_max_window_bits) {
+            (Some(_), None) => return Err(ErrorEnum::BadExtension),
+            (Some(o), Some(r)) if r > o => {
+                return Err(ErrorEnum::BadExtension);
+            }
+            _ => {}
+        }
+        resp.client_no_context_takeover |= offer.client_no_context_takeover;
+        result = Some(resp);
+    }
+    Ok(result)
+}
+
+impl Deflate {
+    /// Chooses the first offer of the client that we can accept
+    ///
+    /// Usually you should use `WebsocketHandshake::deflate()` instead.
+    /// The `extensions` are tokens of `Sec-WebSocket-Extensions` header,
+    /// like in `WebsocketHandshake::extensions`.
+    pub fn accept_offer(extensions: &[String], cfg: &Config)
+        -> Option<Deflate>
+    {
+        for ext in extensions {
+            let offer = match parse(ext) {
+                Ok(Some(offer)) => offer,
+                Ok(None) => continue,
+                Err(()) => {
+                    debug!("Invalid permessage-deflate offer {:?}", ext);
+                    continue;
+                }
+            };
+            // Compressor always uses the largest window
+            if offer.server_max_window_bits.map(|x| x < 15).unwrap_or(false) {
+                continue;
+            }
+            let client_bits = match offer.client_max_window_bits {
+                Some(bits) if cfg.deflate_max_window_bits < bits => {
+                    Some(cfg.deflate_max_window_bits)
+                }
+                _ => None,
+            };
+            return Some(Deflate {
+                server_no_context_takeover: offer.server_no_context_takeover ||
+                    cfg.server_no_context_takeover,
+                client_no_context_takeover: offer.client_no_context_takeover ||
+                    cfg.client_no_context_takeover,
+                server_max_window_bits: offer.server_max_window_bits,
+                client_max_window_bits: client_bits,
+            });
+        }
+        None
+    }
+}
+
+impl fmt::Display for Deflate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(NAME)?;
+        if self.server_no_context_takeover {
+            f.write_str("; server_no_context_takeover")?;
+        }
+        if self.client_no_context_takeover {
+            f.write_str("; client_no_context_takeover")?;
+        }
+        if let Some(bits) = self.server_max_window_bits {
+            write!(f, "; server_max_window_bits={}", bits)?;
+        }
+        if let Some(bits) = self.client_max_window_bits {
+            write!(f, "; client_max_window_bits={}", bits)?;
+        }
+        Ok(())
+    }
+}
+
+impl Compressor {
+    pub fn new(params: &Deflate, server: bool) -> Compressor {
+        Compressor {
+            inner: Compress::new(Compression::Default, false),
+            reset: if server {
+                params.server_no_context_takeover
+            } else {
+                params.client_no_context_takeover
+            },
+        }
+    }
+    pub fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(data.len() / 2 + 64);
+        let mut input = data;
+        loop {
+            let before = self.inner.total_in();
+            self.inner.compress_vec(input, &mut result, Flush::Sync);
+            input = &input[(self.inner.total_in() - before) as usize..];
+            // Sync flush is complete when there is space left in the output
+            if input.len() == 0 && result.len() < result.capacity() {
+                break;
+            }
+            let cap = result.capacity();
+            result.reserve(cap);
+        }
+        if result.ends_with(&TAIL[..]) {
+            let len = result.len() - TAIL.len();
+            result.truncate(len);
+        }
+        if self.reset {
+            self.inner.reset();
+        }
+        return result;
+    }
+}
+
+impl Decompressor {
+    pub fn new(params: &Deflate, server: bool) -> Decompressor {
+        Decompressor {
+            inner: Decompress::new(false),
+            reset: if server {
+                params.client_no_context_takeover
+            } else {
+                params.server_no_context_takeover
+            },
+        }
+    }
+    /// Decompresses a message, `limit` is applied to the decompressed size
+    pub fn decompress(&mut self, data: &[u8], limit: usize)
+        -> Result<Vec<u8>, ErrorEnum>
+    {
+        let mut result = Vec::with_capacity(data.len()*2 + 64);
+        for chunk in [data, &TAIL[..]].iter() {
+            let mut input = *chunk;
+            loop {
+                let in_before = self.inner.total_in();
+                let out_before = self.inner.total_out();
+                let status = self.inner
+                    .decompress_vec(input, &mut result, Flush::Sync)
+                    .map_err(|_| ErrorEnum::DecompressionError)?;
+                input = &input[(self.inner.total_in() - in_before) as usize..];
+                if result.len() > limit {
+                    return Err(ErrorEnum::TooLong);
+                }
+                if matches!(status, Status::StreamEnd) {
+                    // Peer has finished deflate stream, so the next message
+                    // starts a new one
+                    self.inner.reset(false);
+                    break;
+                }
+                if input.len() == 0 && result.len() < result.capacity() {
+                    break;
+                }
+                if result.len() == result.capacity() {
+                    let cap = max(result.capacity(), 1024);
+                    result.reserve(cap);
+                } else if self.inner.total_in() == in_before &&
+                    self.inner.total_out() == out_before
+                {
+                    return Err(ErrorEnum::DecompressionError);
+                }
+            }
+        }
+        if self.reset {
+            self.inner.reset(false);
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use websocket::Config;
+    use websocket::Deflate;
+    use super::{parse, client_offer, check_response};
+    use super::{Compressor, Decompressor};
+
+    fn offers(items: &[&str]) -> Vec<String> {
+        items.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_params() {
+        let d = parse("permessage-deflate; client_max_window_bits; \
+                       server_max_window_bits=\"10\"").unwrap().unwrap();
+        assert_eq!(d.client_max_window_bits, Some(15));
+        assert_eq!(d.server_max_window_bits, Some(10));
+        assert!(parse("x-webkit-deflate-frame").unwrap().is_none());
+        assert!(parse("permessage-deflate; unknown").is_err());
+        assert!(parse("permessage-deflate; server_max_window_bits=16")
+            .is_err());
+    }
+
+    #[test]
+    fn server_accept() {
+        let cfg = Config::new().server_no_context_takeover(true).done();
+        let d = Deflate::accept_offer(&offers(&[
+            "permessage-deflate; server_max_window_bits=10",
+            "permessage-deflate; client_max_window_bits",
+            ]), &cfg).unwrap();
+        assert_eq!(d.to_string(),
+            "permessage-deflate; server_no_context_takeover");
+        assert!(Deflate::accept_offer(&offers(&["foo"]), &cfg).is_none());
+    }
+
+    #[test]
+    fn server_limits_client_window() {
+        let cfg = Config::new().deflate_max_window_bits(10).done();
+        let d = Deflate::accept_offer(&offers(&[
+            "permessage-deflate; client_max_window_bits",
+            ]), &cfg).unwrap();
+        assert_eq!(d.to_string(),
+            "permessage-deflate; client_max_window_bits=10");
+    }
+
+    #[test]
+    fn client_negotiation() {
+        let cfg = Config::new()
+            .server_no_context_takeover(true)
+            .deflate_max_window_bits(12)
+            .done();
+        let offer = client_offer(&cfg);
+        assert_eq!(offer.to_string(), "permessage-deflate; \
+            server_no_context_takeover; server_max_window_bits=12");
+        let resp = "permessage-deflate; server_no_context_takeover; \
+                    server_max_window_bits=11";
+        assert!(check_response(vec![resp].into_iter(), Some(&offer))
+            .unwrap().is_some());
+        assert!(check_response(vec![resp].into_iter(), None).is_err());
+        assert!(check_response(vec!["permessage-deflate"].into_iter(),
+            Some(&offer)).is_err());
+        assert!(check_response(vec![].into_iter(), Some(&offer))
+            .unwrap().is_none());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let cfg = Config::new().done();
+        let params = Deflate::accept_offer(
+            &offers(&["permessage-deflate"]), &cfg).unwrap();
+        let mut comp = Compressor::new(&params, true);
+        let mut decomp = Decompressor::new(&params, false);
+        for _ in 0..3 {
+            let data = comp.compress(b"hello hello hello hello");
+            assert_eq!(decomp.decompress(&data, 100).unwrap(),
+                       b"hello hello hello hello");
+        }
+        let data = comp.compress(&[0; 1000]);
+        assert!(decomp.decompress(&data, 100).is_err());
+    }
+}
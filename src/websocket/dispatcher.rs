@@ -6,11 +6,13 @@ use futures::future::{FutureResult, ok};
 use futures::stream;
 use tokio_core::io::Io;
 use tk_bufstream::{ReadFramed, WriteFramed, ReadBuf, WriteBuf};
-use tk_bufstream::{Encode};
 
 use websocket::{Frame, Config, Packet, Error, ServerCodec, ClientCodec};
+use websocket::Deflate;
 use websocket::error::ErrorEnum;
-use websocket::zero_copy::{parse_frame, write_packet, write_close};
+use websocket::codec::encode;
+use websocket::deflate::Compressor;
+use websocket::zero_copy::{Parser, write_packet, write_close};
 
 
 /// Dispatches messages received from websocket
@@ -36,6 +38,8 @@ pub struct Loop<S: Io, T, D: Dispatcher> {
     config: Arc<Config>,
     input: ReadBuf<S>,
     output: WriteBuf<S>,
+    parser: Parser,
+    compressor: Option<Compressor>,
     stream: Option<T>,
     dispatcher: D,
     backpressure: Option<D::Future>,
@@ -80,6 +84,8 @@ impl<S: Io, T, D, E> Loop<S, T, D>
             config: config.clone(),
             input: inp.into_inner(),
             output: outp.into_inner(),
+            parser: Parser::new(),
+            compressor: None,
             stream: Some(stream),
             dispatcher: dispatcher,
             backpressure: None,
@@ -100,6 +106,8 @@ impl<S: Io, T, D, E> Loop<S, T, D>
             config: config.clone(),
             input: inp.into_inner(),
             output: outp.into_inner(),
+            parser: Parser::new(),
+            compressor: None,
             stream: Some(stream),
             dispatcher: dispatcher,
             backpressure: None,
@@ -107,6 +115,17 @@ impl<S: Io, T, D, E> Loop<S, T, D>
             server: false,
         }
     }
+    /// Enable permessage-deflate with parameters negotiated in handshake
+    ///
+    /// Use `WebsocketHandshake::deflate()` on the server side and
+    /// `client::Head::deflate()` on the client side to get the parameters.
+    /// Note: `Loop` doesn't use codecs passed to the constructors, so
+    /// this method must be called even if codecs have compression enabled.
+    pub fn deflate(mut self, params: &Deflate) -> Loop<S, T, D> {
+        self.parser.set_deflate(params, self.server);
+        self.compressor = Some(Compressor::new(params, self.server));
+        self
+    }
 }
 
 impl<S: Io> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
@@ -138,6 +157,8 @@ impl<S: Io> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
             config: config.clone(),
             input: inp.into_inner(),
             output: out,
+            parser: Parser::new(),
+            compressor: None,
             stream: None,
             dispatcher: BlackHole,
             backpressure: None,
@@ -164,13 +185,9 @@ impl<S: Io, T, D, E> Loop<S, T, D>
                 match stream.poll()? {
                     Async::Ready(value) => match value {
                         Some(pkt) => {
-                            if self.server {
-                                ServerCodec.encode(pkt,
-                                    &mut self.output.out_buf);
-                            } else {
-                                ClientCodec.encode(pkt,
-                                    &mut self.output.out_buf);
-                            }
+                            encode(pkt, &mut self.output.out_buf,
+                                !self.server, self.config.fragment_size,
+                                self.compressor.as_mut());
                         }
                         None => {
                             match self.state {
@@ -228,7 +245,7 @@ impl<S: Io, T, D, E> Future for Loop<S, T, D>
         loop {
             while self.input.in_buf.len() > 0 {
                 let (fut, nbytes) = match
-                    parse_frame(&mut self.input.in_buf,
+                    self.parser.parse(&mut self.input.in_buf,
                                 self.config.max_packet_size, self.server)?
                 {
                     Some((frame, nbytes)) => {
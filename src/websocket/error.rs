@@ -34,13 +34,30 @@ quick_error! {
         Unmasked {
             description("Received unmasked frame")
         }
-        /// Got fragmented frame (fragmented frames are not supported yet)
+        /// Fragmented message is malformed
+        ///
+        /// This means a continuation frame without a message start, a new
+        /// message started before previous one is finished or a fragmented
+        /// control frame.
         Fragmented {
-            description("Received fragmented frame")
+            description("Received invalid fragmented frame")
+        }
+        /// Received frame with reserved bits set, that are not allowed by
+        /// negotiated extensions
+        ReservedBits {
+            description("Received frame with reserved bits set")
+        }
+        /// Compressed message can't be decompressed
+        DecompressionError {
+            description("Error decompressing message")
+        }
+        /// Server accepted extension in a way we didn't offer
+        BadExtension {
+            description("Server responded with invalid extension")
         }
-        /// Received frame that is longer than configured limit
+        /// Received message that is longer than configured limit
         TooLong {
-            description("Received frame that is too long")
+            description("Received message that is too long")
         }
         /// Currently this error means that channel to/from websocket closed
         ///
@@ -62,6 +79,26 @@ quick_error! {
         PrematureResponseHeaders {
             description("response headers before request are sent")
         }
+        /// Server responded with HTTP/1.0, which doesn't support upgrades
+        VersionTooOld {
+            description("websocket requires HTTP/1.1 or newer")
+            display("websocket requires HTTP/1.1 or newer")
+        }
+        /// Server responded to the handshake with status other than 101
+        InvalidStatus(code: u16) {
+            description("server didn't switch protocols")
+            display("server didn't switch protocols, status: {}", code)
+        }
+        /// Response has no `Upgrade: websocket` or `Connection: upgrade`
+        InvalidUpgrade {
+            description("response has no websocket upgrade headers")
+            display("response has no websocket upgrade headers")
+        }
+        /// `Sec-WebSocket-Accept` is missing or doesn't match the key sent
+        InvalidAccept {
+            description("invalid Sec-WebSocket-Accept header")
+            display("invalid Sec-WebSocket-Accept header")
+        }
         Custom(err: Box<::std::error::Error + Send + Sync>) {
             description("custom error")
             cause(&**err)
@@ -7,6 +7,7 @@ use std::time::Duration;
 mod alloc;
 mod codec;
 mod config;
+mod deflate;
 mod dispatcher;
 mod error;
 mod keys;
@@ -15,6 +16,7 @@ pub mod client;
 
 pub use self::alloc::Packet;
 pub use self::codec::{ServerCodec, ClientCodec};
+pub use self::deflate::Deflate;
 pub use self::dispatcher::{Loop, Dispatcher};
 pub use self::error::Error;
 pub use self::keys::{GUID, Accept, Key};
@@ -28,4 +30,8 @@ pub struct Config {
     ping_interval: Duration,
     inactivity_timeout: Duration,
     max_packet_size: usize,
+    fragment_size: Option<usize>,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    deflate_max_window_bits: u8,
 }
@@ -5,6 +5,7 @@ use tk_bufstream::Buf;
 use byteorder::{BigEndian, ByteOrder};
 
 use super::{Packet};
+use websocket::deflate::{Deflate, Decompressor};
 use websocket::error::ErrorEnum;
 
 
@@ -52,11 +53,33 @@ impl<'a> Into<Packet> for &'a Frame<'a> {
 }
 
 
-pub fn parse_frame<'x>(buf: &'x mut Buf, limit: usize, masked: bool)
-    -> Result<Option<(Frame<'x>, usize)>, ErrorEnum>
-{
-    use self::Frame::*;
+/// Reassembles fragmented messages
+///
+/// Fragments are copied into an internal buffer, so this only costs
+/// something for messages which are really fragmented. Control frames
+/// are allowed in the middle of the fragmented message, they are returned
+/// immediately.
+///
+/// Compressed messages (permessage-deflate) are decompressed into the same
+/// internal buffer.
+pub struct Parser {
+    /// Opcode of the first frame of the message being reassembled
+    opcode: Option<u8>,
+    /// Whether message being reassembled is compressed
+    compressed: bool,
+    data: Vec<u8>,
+    /// Message in `data` is already returned to the caller
+    complete: bool,
+    decompressor: Option<Decompressor>,
+}
 
+/// Parses frame header and unmasks data in place
+///
+/// Returns `(fin, rsv_bits, opcode, data_offset, data_size)` when the whole
+/// frame is in the buffer
+fn parse_header(buf: &mut Buf, limit: usize, masked: bool)
+    -> Result<Option<(bool, u8, u8, usize, usize)>, ErrorEnum>
+{
     if buf.len() < 2 {
         return Ok(None);
     }
@@ -87,12 +110,9 @@ pub fn parse_frame<'x>(buf: &'x mut Buf, limit: usize, masked: bool)
     }
 
     let fin = buf[0] & 0x80 != 0;
+    let rsv = buf[0] & 0x70;
     let opcode = buf[0] & 0x0F;
-    // TODO(tailhook) should we assert that reserved bits are zero?
     let mask = buf[1] & 0x80 != 0;
-    if !fin {
-        return Err(ErrorEnum::Fragmented);
-    }
     if mask != masked {
         return Err(ErrorEnum::Unmasked);
     }
@@ -102,22 +122,131 @@ pub fn parse_frame<'x>(buf: &'x mut Buf, limit: usize, masked: bool)
             buf[start + idx] ^= mask[idx % 4];
         }
     }
-    let data = &buf[start..(start + size)];
+    return Ok(Some((fin, rsv, opcode, start, size)));
+}
+
+fn make_frame(opcode: u8, data: &[u8]) -> Result<Frame, ErrorEnum> {
+    use self::Frame::*;
     let frame = match opcode {
         0x9 => Ping(data),
         0xA => Pong(data),
         0x1 => Text(from_utf8(data)?),
         0x2 => Binary(data),
         // TODO(tailhook) implement shutdown packets
-        0x8 => Close(BigEndian::read_u16(&data[..2]), from_utf8(&data[2..])?),
+        0x8 if data.len() >= 2 => {
+            Close(BigEndian::read_u16(&data[..2]), from_utf8(&data[2..])?)
+        }
+        // Close frame without a status code
+        0x8 => Close(1005, ""),
         x => return Err(ErrorEnum::InvalidOpcode(x)),
     };
-    return Ok(Some((frame, start + size)));
+    return Ok(frame);
 }
 
-pub fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool) {
-    debug_assert!(opcode & 0xF0 == 0);
-    let first_byte = opcode | 0x80;  // always fin
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            opcode: None,
+            compressed: false,
+            data: Vec::new(),
+            complete: false,
+            decompressor: None,
+        }
+    }
+    /// Enable decompression of messages which have RSV1 bit set
+    pub fn set_deflate(&mut self, params: &Deflate, server: bool) {
+        self.decompressor = Some(Decompressor::new(params, server));
+    }
+    /// Parses next message from the buffer
+    ///
+    /// Returns a frame and the number of bytes the caller should consume
+    /// from the buffer after processing the frame. Fragments of unfinished
+    /// message are consumed from the buffer by the parser itself.
+    ///
+    /// The `limit` is applied to the total size of fragmented message, and
+    /// also to the size of the message after decompression.
+    pub fn parse<'x>(&'x mut self, buf: &'x mut Buf,
+        limit: usize, masked: bool)
+        -> Result<Option<(Frame<'x>, usize)>, ErrorEnum>
+    {
+        if self.complete {
+            // Don't keep large buffer for the whole life of connection
+            self.data = Vec::new();
+            self.complete = false;
+        }
+        loop {
+            let limit_left = limit - self.data.len();
+            let (fin, rsv, opcode, start, size) =
+                match parse_header(buf, limit_left, masked)? {
+                    Some(x) => x,
+                    None => return Ok(None),
+                };
+            // RSV1 marks compressed message, it's only allowed on the
+            // first frame of the data message
+            let rsv1 = rsv == 0x40;
+            if rsv != 0 && (!rsv1 || self.decompressor.is_none() ||
+                            opcode & 0x8 != 0 || opcode == 0x0)
+            {
+                return Err(ErrorEnum::ReservedBits);
+            }
+            match (opcode, self.opcode) {
+                (0x8...0xF, _) => {
+                    // Control frames can be interleaved with fragments,
+                    // but can't be fragmented themselves
+                    if !fin {
+                        return Err(ErrorEnum::Fragmented);
+                    }
+                    let frame = make_frame(opcode, &buf[start..start+size])?;
+                    return Ok(Some((frame, start + size)));
+                }
+                (0x0, None) => return Err(ErrorEnum::Fragmented),
+                (0x0, Some(first)) => {
+                    self.data.extend_from_slice(&buf[start..start+size]);
+                    if fin {
+                        self.opcode = None;
+                        self.complete = true;
+                        if self.compressed {
+                            self.compressed = false;
+                            let data = self.decompressor.as_mut()
+                                .expect("compression enabled")
+                                .decompress(&self.data, limit)?;
+                            self.data = data;
+                        }
+                        let frame = make_frame(first, &self.data)?;
+                        return Ok(Some((frame, start + size)));
+                    }
+                }
+                (_, Some(_)) => return Err(ErrorEnum::Fragmented),
+                (_, None) if fin && rsv1 => {
+                    let data = self.decompressor.as_mut()
+                        .expect("compression enabled")
+                        .decompress(&buf[start..start+size], limit)?;
+                    self.data = data;
+                    self.complete = true;
+                    let frame = make_frame(opcode, &self.data)?;
+                    return Ok(Some((frame, start + size)));
+                }
+                (_, None) if fin => {
+                    let frame = make_frame(opcode, &buf[start..start+size])?;
+                    return Ok(Some((frame, start + size)));
+                }
+                (0x1, None) | (0x2, None) => {
+                    self.opcode = Some(opcode);
+                    self.compressed = rsv1;
+                    self.data.extend_from_slice(&buf[start..start+size]);
+                }
+                (x, None) => return Err(ErrorEnum::InvalidOpcode(x)),
+            }
+            buf.consume(start + size);
+        }
+    }
+}
+
+fn write_frame(buf: &mut Buf, fin: bool, opcode: u8, data: &[u8], mask: bool)
+{
+    // opcode may also contain RSV1 bit for compressed messages
+    debug_assert!(opcode & 0xB0 == 0);
+    let first_byte = if fin { opcode | 0x80 } else { opcode };
     let mask_bit = if mask { 0x80 } else { 0 };
     match data.len() {
         len @ 0...125 => {
@@ -155,6 +284,32 @@ pub fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool) {
     };
 }
 
+pub fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool) {
+    write_frame(buf, true, opcode, data, mask)
+}
+
+/// Write a text or binary message splitting it into frames of at most
+/// `fragment_size` bytes
+///
+/// If `compressed` is true, RSV1 bit is set on the first frame
+pub fn write_message(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool,
+    fragment_size: Option<usize>, compressed: bool)
+{
+    let opcode = if compressed { opcode | 0x40 } else { opcode };
+    match fragment_size {
+        Some(size) if data.len() > size => {
+            let mut chunks = data.chunks(size).peekable();
+            let mut opcode = opcode;
+            while let Some(chunk) = chunks.next() {
+                let fin = chunks.peek().is_none();
+                write_frame(buf, fin, opcode, chunk, mask);
+                opcode = 0x0;  // continuation
+            }
+        }
+        _ => write_frame(buf, true, opcode, data, mask),
+    }
+}
+
 /// Write close message to websocket
 pub fn write_close(buf: &mut Buf, code: u16, reason: &str, mask: bool) {
     let data = reason.as_bytes();
@@ -177,3 +332,110 @@ pub fn write_close(buf: &mut Buf, code: u16, reason: &str, mask: bool) {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use tk_bufstream::Buf;
+
+    use super::{Parser, Frame, write_packet, write_message};
+    use websocket::{Config, Deflate};
+    use websocket::deflate::Compressor;
+    use websocket::error::ErrorEnum;
+
+    #[test]
+    fn fragmented_text() {
+        let mut buf = Buf::new();
+        write_message(&mut buf, 0x1, "hello world".as_bytes(), true,
+                      Some(4), false);
+        let mut parser = Parser::new();
+        let len = match parser.parse(&mut buf, 100, true).unwrap() {
+            Some((Frame::Text("hello world"), len)) => len,
+            x => panic!("Wrong frame {:?}", x),
+        };
+        buf.consume(len);
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn ping_between_fragments() {
+        let mut buf = Buf::new();
+        buf.extend(&[0x02, 0x02, 1, 2]);  // binary, no fin
+        write_packet(&mut buf, 0x9, b"x", false);
+        buf.extend(&[0x80, 0x01, 3]);  // continuation, fin
+        let mut parser = Parser::new();
+        let len = match parser.parse(&mut buf, 100, false).unwrap() {
+            Some((Frame::Ping(data), len)) => {
+                assert_eq!(data, b"x");
+                len
+            }
+            x => panic!("Wrong frame {:?}", x),
+        };
+        buf.consume(len);
+        let len = match parser.parse(&mut buf, 100, false).unwrap() {
+            Some((Frame::Binary(data), len)) => {
+                assert_eq!(data, &[1, 2, 3][..]);
+                len
+            }
+            x => panic!("Wrong frame {:?}", x),
+        };
+        buf.consume(len);
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn incomplete_message() {
+        let mut buf = Buf::new();
+        buf.extend(&[0x01, 0x02, b'a', b'b']);  // text, no fin
+        let mut parser = Parser::new();
+        assert!(parser.parse(&mut buf, 100, false).unwrap().is_none());
+        buf.extend(&[0x80, 0x01, b'c']);
+        let len = match parser.parse(&mut buf, 100, false).unwrap() {
+            Some((Frame::Text("abc"), len)) => len,
+            x => panic!("Wrong frame {:?}", x),
+        };
+        assert_eq!(len, 3);
+    }
+
+    #[test]
+    fn total_size_limit() {
+        let mut buf = Buf::new();
+        write_message(&mut buf, 0x2, &[0; 10], false, Some(4), false);
+        let mut parser = Parser::new();
+        assert!(matches!(parser.parse(&mut buf, 8, false),
+                         Err(ErrorEnum::TooLong)));
+    }
+
+    #[test]
+    fn unexpected_continuation() {
+        let mut buf = Buf::new();
+        buf.extend(&[0x80, 0x01, 1]);
+        let mut parser = Parser::new();
+        assert!(matches!(parser.parse(&mut buf, 100, false),
+                         Err(ErrorEnum::Fragmented)));
+    }
+
+    #[test]
+    fn compressed_fragments() {
+        let params = Deflate::accept_offer(
+            &["permessage-deflate".to_string()], &Config::new()).unwrap();
+        let mut compressor = Compressor::new(&params, false);
+        let mut buf = Buf::new();
+        let data = compressor.compress(b"hello hello hello");
+        write_message(&mut buf, 0x1, &data, true, Some(3), true);
+        let mut parser = Parser::new();
+        parser.set_deflate(&params, true);
+        match parser.parse(&mut buf, 100, true).unwrap() {
+            Some((Frame::Text("hello hello hello"), _)) => {}
+            x => panic!("Wrong frame {:?}", x),
+        }
+    }
+
+    #[test]
+    fn reserved_bits() {
+        let mut buf = Buf::new();
+        buf.extend(&[0xC1, 0x01, b'a']);  // RSV1 without negotiation
+        let mut parser = Parser::new();
+        assert!(matches!(parser.parse(&mut buf, 100, false),
+                         Err(ErrorEnum::ReservedBits)));
+    }
+}